@@ -1,22 +1,72 @@
+// `processing.rs` is both the root of the `processing` binary and `crate::bin::processing` in
+// the server, so its submodules need explicit paths to resolve the same way in both.
+#[path = "processing/append.rs"]
+pub mod append;
+#[path = "processing/cli.rs"]
+pub mod cli;
+#[path = "processing/dedup.rs"]
+pub mod dedup;
+#[path = "processing/edit.rs"]
+pub mod edit;
+#[path = "processing/fields.rs"]
+pub mod fields;
+#[path = "processing/ingest.rs"]
+pub mod ingest;
+#[path = "processing/input.rs"]
+pub mod input;
+#[path = "processing/labels.rs"]
+pub mod labels;
+#[path = "processing/leakage.rs"]
+pub mod leakage;
+#[path = "processing/loader.rs"]
+pub mod loader;
+#[path = "processing/lsh.rs"]
+pub mod lsh;
+#[path = "processing/metadata.rs"]
+pub mod metadata;
+#[path = "processing/normalize.rs"]
+pub mod normalize;
+#[path = "processing/reports.rs"]
+pub mod reports;
+#[path = "processing/simhash.rs"]
+pub mod simhash;
+#[cfg(test)]
+#[path = "processing/test_support.rs"]
+pub mod test_support;
+#[path = "processing/weighted.rs"]
+pub mod weighted;
+
+use cli::Args;
 use csv;
-use rand::Rng;
+use labels::LabelSet;
+use loader::{LoadError, LoadOptions, RecordLoader};
+use metadata::Metadata;
+use normalize::Normalization;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
 use rusqlite::Connection;
 use serde_json::json;
 use std::{
     cmp::min,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::{self, Write},
     fs::File,
-    hash::Hash,
     path::Path,
 };
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Hash, Clone)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
 pub struct Record {
     pub id: u32,
     pub title: String,
     pub text: String,
-    pub label: u32, // can be either 1 or 0
+    /// id in the `labels` table
+    pub label: u32,
+    /// Name of `label`, `None` for databases built before labels had names.
+    #[serde(default)]
+    pub label_name: Option<String>,
+    /// Extra source columns, stored in `record_metadata` rather than the `records` table.
+    #[serde(default)]
+    pub metadata: Metadata,
 }
 
 impl Record {
@@ -31,88 +81,322 @@ impl Record {
     }
 }
 
-impl<'a> From<&tokio_rusqlite::Row<'a>> for Record {
-    fn from(value: &tokio_rusqlite::Row<'a>) -> Self {
-        Record {
-            id: value.get(0).unwrap(),
-            title: value.get(1).unwrap(),
-            text: value.get(2).unwrap(),
-            label: value.get::<_, String>(3).unwrap().parse().unwrap(),
+/// Reads a row of the `records` table. A malformed row is an error rather than a panic, so it
+/// can't take the server down.
+impl<'a> TryFrom<&tokio_rusqlite::Row<'a>> for Record {
+    type Error = rusqlite::Error;
+
+    fn try_from(value: &tokio_rusqlite::Row<'a>) -> Result<Self, Self::Error> {
+        let label: String = value.get(3)?;
+        Ok(Record {
+            id: value.get(0)?,
+            title: value.get(1)?,
+            text: value.get(2)?,
+            label: label.parse().map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    3,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            })?,
+            label_name: None,
+            metadata: Metadata::new(),
+        })
+    }
+}
+
+/// One entry of the `similar_documents` column in the `similarities` table.
+///
+/// `containment` is how much of the row's document is found in `doc_id`, and
+/// `reverse_containment` how much of `doc_id` is found in the row's document. Databases built
+/// before containment was tracked only have `similarity`.
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone)]
+pub struct SimilarDocument {
+    pub doc_id: u32,
+    pub similarity: f64,
+    #[serde(default)]
+    pub containment: Option<f64>,
+    #[serde(default)]
+    pub reverse_containment: Option<f64>,
+}
+
+/// Similarity measures between two documents `a` and `b`.
+#[derive(Debug, serde::Serialize, Clone, Copy, PartialEq)]
+pub struct PairSimilarity {
+    /// Estimated Jaccard similarity |A∩B| / |A∪B|.
+    pub similarity: f64,
+    /// |A∩B| / |A|, close to 1 when `a` is embedded in `b`.
+    pub containment: f64,
+    /// |A∩B| / |B|, close to 1 when `b` is embedded in `a`.
+    pub reverse_containment: f64,
+}
+
+impl PairSimilarity {
+    /// Pairs a Jaccard estimate with both containment directions counted from the shingle sets
+    /// themselves. A Jaccard estimate is close to 0 for a short document inside a much longer
+    /// one, so containment can't be derived from it.
+    pub fn from_shingles(
+        similarity: f64,
+        a: &HashSet<String>,
+        b: &HashSet<String>,
+    ) -> PairSimilarity {
+        let (smaller, larger) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+        let shared = smaller
+            .iter()
+            .filter(|shingle| larger.contains(*shingle))
+            .count();
+        let ratio = |size: usize| {
+            if size == 0 {
+                0.0
+            } else {
+                shared as f64 / size as f64
+            }
+        };
+
+        PairSimilarity {
+            similarity,
+            containment: ratio(a.len()),
+            reverse_containment: ratio(b.len()),
+        }
+    }
+
+    /// Derives both containment directions from a Jaccard estimate and the total weights of the
+    /// documents, using |A∩B| = J * (|A| + |B|) / (1 + J). Only for weighted MinHash, which has
+    /// no sets to count.
+    pub fn estimate(jaccard: f64, size_a: f64, size_b: f64) -> PairSimilarity {
+        let intersection = jaccard * (size_a + size_b) / (1.0 + jaccard);
+        let ratio = |size: f64| {
+            if size <= 0.0 {
+                0.0
+            } else {
+                (intersection / size).min(1.0)
+            }
+        };
+
+        PairSimilarity {
+            similarity: jaccard,
+            containment: ratio(size_a),
+            reverse_containment: ratio(size_b),
+        }
+    }
+
+    pub fn score(&self, metric: SimilarityMetric) -> f64 {
+        match metric {
+            SimilarityMetric::Jaccard => self.similarity,
+            SimilarityMetric::Containment => self.containment.max(self.reverse_containment),
+        }
+    }
+}
+
+/// Which measure is compared against the similarity threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimilarityMetric {
+    Jaccard,
+    /// Either document is (mostly) contained in the other, e.g. an excerpt of a longer article.
+    Containment,
+}
+
+impl fmt::Display for SimilarityMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimilarityMetric::Jaccard => write!(f, "jaccard"),
+            SimilarityMetric::Containment => write!(f, "containment"),
         }
     }
 }
 
-pub type InverseIndexDB = HashMap<String, HashSet<u32>>;
+impl std::str::FromStr for SimilarityMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jaccard" => Ok(SimilarityMetric::Jaccard),
+            "containment" => Ok(SimilarityMetric::Containment),
+            _ => Err(format!("unknown similarity metric `{}`", s)),
+        }
+    }
+}
+
+/// Near-duplicate algorithm used by the `process` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// MinHash signatures over character shingles, compared pairwise.
+    MinHash,
+    /// 64 bit SimHash fingerprints, compared within Hamming-distance buckets.
+    SimHash,
+    /// Consistent weighted sampling over token weights, so repeated content counts more.
+    WeightedMinHash,
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minhash" => Ok(Algorithm::MinHash),
+            "simhash" => Ok(Algorithm::SimHash),
+            "weighted-minhash" => Ok(Algorithm::WeightedMinHash),
+            _ => Err(format!("unknown algorithm `{}`", s)),
+        }
+    }
+}
+
+pub type InverseIndexDB = HashMap<String, BTreeSet<u32>>;
+pub type SimilaritiesDB = HashMap<u32, HashMap<u32, PairSimilarity>>;
+
+pub const DEFAULT_DB_FILE: &str = "processed.db";
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.85;
 
 #[allow(dead_code)]
 fn main() {
+    let args = Args::from_env();
+
+    // 0 (the default) lets rayon use every core
+    let threads = args.parse_or("threads", 0);
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .expect("Failed to build the thread pool");
+
+    match args.command.as_deref() {
+        None | Some("process") => run_processing(&args),
+        Some("label-conflicts") => reports::run_label_conflicts(&args),
+        Some("leakage") => leakage::run_leakage(&args),
+        Some("index") => append::run_index(&args),
+        Some("append") => append::run_append(&args),
+        Some("update") => edit::run_update(&args),
+        Some("delete") => edit::run_delete(&args),
+        Some(other) => cli::exit_with_usage(&format!("unknown command `{}`", other)),
+    }
+}
+
+fn run_processing(args: &Args) {
     let shingle_size = 3;
     let minhash_length = 20;
-    let similarity_threshold = 0.85;
-    let file_path =
-        Path::new("/home/devnull03/school/COMP455/project/server/src/bin/evaluation.csv");
+    let similarity_threshold = args.parse_or("min-similarity", DEFAULT_SIMILARITY_THRESHOLD);
+    let similarity_metric = args.parse_or("metric", SimilarityMetric::Jaccard);
+    let algorithm = args.parse_or("algorithm", Algorithm::MinHash);
+    let weighting = args.parse_or("weighting", weighted::Weighting::Tf);
+    let normalization = args.parse_or("normalize", Normalization::default());
+    let seed = seed_from_args(args);
+    let input_file = args.get_or(
+        "input",
+        "/home/devnull03/school/COMP455/project/server/src/bin/evaluation.csv",
+    );
+    let file_path = Path::new(&input_file);
+    let db_file = args.get_or("db", DEFAULT_DB_FILE);
+    let similarities_file_path = args.get_or(
+        "similarities-csv",
+        "/home/devnull03/school/COMP455/project/server/src/bin/similarities.csv",
+    );
 
-    let mut minhash_data: HashMap<u32, Vec<u64>> = HashMap::new();
-    let mut similarities: HashMap<u32, HashMap<u32, f64>> = HashMap::new();
+    // recorded in the database so records appended later are compared the same way
+    let settings = ingest::IngestSettings {
+        shingle_size,
+        minhash_length,
+        bands: args.parse_or("bands", 5),
+        seed,
+        metric: similarity_metric,
+        threshold: similarity_threshold,
+        normalization,
+    };
+
+    if args.has("streaming") {
+        if algorithm != Algorithm::MinHash {
+            cli::exit_with_usage("--streaming only supports --algorithm minhash");
+        }
+
+        ingest::run_streaming(args, settings, file_path, &db_file, &similarities_file_path);
+        return;
+    }
 
     // ------------------------------------------------------------------------------------------------------------------------------------------
     //? loading the data
 
     println!("Loading file in memory....");
-    let records = load_data(file_path);
-    let combined_strings: Vec<String> = records
-        .values()
-        .map(|record| record.to_db_string())
+    let (records, label_set) = load_data(file_path, &LoadOptions::from_args(args));
+    // keyed by record id so the similarities table points at real records
+    let combined_strings: HashMap<u32, String> = records
+        .iter()
+        .map(|(id, record)| (*id, record.to_db_string()))
+        .collect();
+
+    // indexing and shingling see the normalized text, the records table keeps the original
+    println!("Normalizing text ({})...", normalization);
+    let normalized_records: HashMap<u32, Record> = records
+        .par_iter()
+        .map(|(id, record)| (*id, normalization.record(record)))
         .collect();
 
     println!("Creating the inverse idex...");
-    let inverse_index: HashMap<String, HashSet<u32>> = build_inverted_index(&records);
+    let inverse_index: InverseIndexDB = build_inverted_index(&normalized_records);
+    let field_index = fields::build_field_index(&normalized_records);
 
     // ------------------------------------------------------------------------------------------------------------------------------------------
     //? prepairing the data
 
-    println!("Creating shingles..");
-    let shingled_dataset = create_shingles(&combined_strings, shingle_size);
+    println!("Finding exact duplicates...");
+    let duplicate_groups = dedup::find_exact_duplicates(&normalized_records);
+    println!(
+        "{} exact duplicate groups covering {} records",
+        duplicate_groups.len(),
+        duplicate_groups.values().map(Vec::len).sum::<usize>()
+    );
+    // only one representative per group goes through the similarity stage
+    let skipped_duplicates: HashSet<u32> = duplicate_groups
+        .values()
+        .flat_map(|members| members.iter().skip(1).copied())
+        .collect();
+    let similarity_inputs: HashMap<u32, String> = normalized_records
+        .iter()
+        .filter(|(id, _)| !skipped_duplicates.contains(id))
+        .map(|(id, record)| (*id, record.to_db_string()))
+        .collect();
 
-    println!("Generating Hash functions..");
-    let hash_funcs = generate_hash_funcs(minhash_length);
+    println!("Creating shingles..");
+    let shingled_dataset = create_shingles(&similarity_inputs, shingle_size);
 
     // ------------------------------------------------------------------------------------------------------------------------------------------
     //? actually processing the data
 
-    println!("Generating minhashes and compairing...");
-    for (doc1_id, doc1) in &shingled_dataset {
-        let doc1_minhash;
-        if let Some(_doc1_minhash) = minhash_data.get(doc1_id).cloned() {
-            doc1_minhash = _doc1_minhash;
-        } else {
-            doc1_minhash = generate_minhash_signature(doc1, &hash_funcs);
-            minhash_data.insert(*doc1_id, doc1_minhash.clone());
+    let mut similarities = match algorithm {
+        Algorithm::MinHash => {
+            println!("Generating minhashes and compairing...");
+            minhash_similarities(
+                &shingled_dataset,
+                minhash_length,
+                seed,
+                similarity_metric,
+                similarity_threshold,
+            )
         }
-
-        let mut doc_similarities: HashMap<u32, f64> = HashMap::new();
-
-        // println!("processing {:?}", doc1_id);
-
-        for (doc2_id, doc2) in &shingled_dataset {
-            let doc2_minhash;
-            if let Some(_doc2_minhash) = minhash_data.get(doc2_id).cloned() {
-                doc2_minhash = _doc2_minhash;
-            } else {
-                doc2_minhash = generate_minhash_signature(doc2, &hash_funcs);
-                minhash_data.insert(*doc2_id, doc2_minhash.clone());
-            }
-
-            // let e = jaccard(doc1, doc2);
-            let e = minhash_similarity(&doc1_minhash, &doc2_minhash); // gives better similarity ratings?
-
-            if e >= similarity_threshold {
-                doc_similarities.insert(*doc2_id, e);
-            }
+        Algorithm::SimHash => {
+            println!("Generating simhashes and compairing...");
+            simhash::simhash_similarities(
+                &shingled_dataset,
+                similarity_metric,
+                similarity_threshold,
+            )
+        }
+        Algorithm::WeightedMinHash => {
+            println!("Generating weighted minhashes and compairing...");
+            let documents: HashMap<u32, String> = normalized_records
+                .iter()
+                .filter(|(id, _)| !skipped_duplicates.contains(id))
+                .map(|(id, record)| (*id, format!("{} {}", record.title, record.text)))
+                .collect();
+            weighted::weighted_minhash_similarities(
+                &documents,
+                weighting,
+                minhash_length,
+                seed,
+                similarity_metric,
+                similarity_threshold,
+            )
         }
+    };
 
-        similarities.insert(*doc1_id, doc_similarities);
-    }
+    dedup::expand_exact_duplicates(&mut similarities, &duplicate_groups);
 
     println!("Finished calculating similarities");
 
@@ -120,34 +404,97 @@ fn main() {
     //? create files for data storage
 
     println!("Generating the csv file");
-    let similarities_file_path =
-        "/home/devnull03/school/COMP455/project/server/src/bin/similarities.csv";
-    create_csv_file(similarities_file_path, &similarities);
+    create_csv_file(&similarities_file_path, &similarities);
 
     println!("Generating the sqlite database");
     create_sqlite_file(
-        "processed.db",
-        &combined_strings,
+        &db_file,
+        &combined_strings
+            .into_iter()
+            .collect::<BTreeMap<u32, String>>()
+            .into_values()
+            .collect(),
         &inverse_index,
         &similarities,
     );
 
+    println!("Storing record metadata, labels and settings");
+    let db_connection = Connection::open(&db_file).unwrap();
+    let tx = db_connection.unchecked_transaction().unwrap();
+    metadata::save(&tx, records.values()).expect("Failed to store record metadata");
+    label_set.save(&tx).expect("Failed to store labels");
+    settings.save(&tx).expect("Failed to store settings");
+    tx.commit().unwrap();
+    fields::save_index(&db_connection, &field_index).expect("Failed to store the field index");
+
     // ------------------------------------------------------------------------------------------------------------------------------------------
 }
 
+/// Reads `--seed`, or picks a random one. Runs with the same seed and input produce the same
+/// output regardless of `--threads`.
+pub fn seed_from_args(args: &Args) -> u64 {
+    let seed = args.parse_or("seed", rand::random());
+    println!("Using seed {}", seed);
+    seed
+}
+
+pub fn minhash_similarities(
+    shingled_dataset: &HashMap<u32, HashSet<String>>,
+    minhash_length: usize,
+    seed: u64,
+    similarity_metric: SimilarityMetric,
+    similarity_threshold: f64,
+) -> SimilaritiesDB {
+    println!("Generating Hash functions..");
+    let hash_funcs = generate_hash_funcs(minhash_length, &mut StdRng::seed_from_u64(seed));
+
+    let minhash_data: HashMap<u32, Vec<u64>> = shingled_dataset
+        .par_iter()
+        .map(|(doc_id, doc)| (*doc_id, generate_minhash_signature(doc, &hash_funcs)))
+        .collect();
+
+    // every worker scores one document against the whole dataset
+    shingled_dataset
+        .par_iter()
+        .map(|(doc1_id, doc1)| {
+            let doc1_minhash = &minhash_data[doc1_id];
+            let mut doc_similarities: HashMap<u32, PairSimilarity> = HashMap::new();
+
+            for (doc2_id, doc2) in shingled_dataset {
+                // let e = jaccard(doc1, doc2);
+                let e = minhash_similarity(doc1_minhash, &minhash_data[doc2_id]); // gives better similarity ratings?
+
+                // counting shared shingles is only needed for pairs that can still pass
+                if similarity_metric == SimilarityMetric::Jaccard && e < similarity_threshold {
+                    continue;
+                }
+                let pair = PairSimilarity::from_shingles(e, doc1, doc2);
+
+                if pair.score(similarity_metric) >= similarity_threshold {
+                    doc_similarities.insert(*doc2_id, pair);
+                }
+            }
+
+            (*doc1_id, doc_similarities)
+        })
+        .collect()
+}
+
 pub fn create_sqlite_file(
     file_name: &str,
     records: &Vec<String>,
     inverse_index: &InverseIndexDB,
-    similarities: &HashMap<u32, HashMap<u32, f64>>,
+    similarities: &SimilaritiesDB,
 ) {
     let records_table_headers = vec!["id", "title", "text", "label"];
     let (records_new_table, records_table_values) =
         build_table_creation_commands("records", &records_table_headers, &records);
 
     let inverse_index_headers = vec!["string", "entries"];
+    // everything is written in key order so the same input always gives the same file
     let inverse_index_string_values: Vec<String> = inverse_index
-        // .values()
+        .iter()
+        .collect::<BTreeMap<_, _>>()
         .into_iter()
         .map(|v| {
             format!(
@@ -172,16 +519,24 @@ pub fn create_sqlite_file(
     let similarities_headers = vec!["document_id", "similar_documents"];
     let mut similarities_values: Vec<String> = Vec::new();
 
-    for doc in similarities {
+    for doc in similarities.iter().collect::<BTreeMap<_, _>>() {
         let mut val = "[".to_string();
         let doc_len = doc.1.len();
-        for (idx, ele) in doc.1.into_iter().enumerate() {
+        for (idx, ele) in doc
+            .1
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .enumerate()
+        {
             write!(
                 &mut val,
                 "{}",
                 json!({
                     "doc_id": ele.0,
-                    "similarity": ele.1
+                    "similarity": ele.1.similarity,
+                    "containment": ele.1.containment,
+                    "reverse_containment": ele.1.reverse_containment,
                 })
                 .to_string()
             )
@@ -273,40 +628,68 @@ pub fn build_table_creation_commands(
     (table_creation_string, insert_statements)
 }
 
-pub fn create_csv_file(
-    similarities_file_path: &str,
-    similarities: &HashMap<u32, HashMap<u32, f64>>,
-) {
-    let similarities_file = File::create(similarities_file_path).expect("Failed to create file");
-    let mut writer = csv::Writer::from_writer(similarities_file);
-    writer
-        .write_record(&["document_id", "similar_documents"])
-        .expect("Failed to write header");
+/// Parses the `similar_documents` column written by `create_sqlite_file`.
+pub fn parse_similar_documents(raw: &str) -> Vec<SimilarDocument> {
+    // entries are written with a trailing comma, e.g. `[{..},{..},]`
+    let raw = raw.trim().trim_end_matches(",]").trim_end_matches(']');
+    let raw = raw.strip_prefix('[').unwrap_or(raw);
+
+    serde_json::from_str(&format!("[{}]", raw.trim_end_matches(','))).unwrap_or_default()
+}
+
+pub fn create_csv_file(similarities_file_path: &str, similarities: &SimilaritiesDB) {
+    let mut writer = SimilaritiesCsv::create(similarities_file_path);
+    for (doc1_id, similar_docs) in similarities.iter().collect::<BTreeMap<_, _>>() {
+        writer.write(*doc1_id, similar_docs.iter().map(|(id, pair)| (*id, *pair)));
+    }
+    writer.finish();
+}
 
-    for (doc1_id, similar_docs) in similarities {
+/// The similarities csv: one row per document, with its similar documents in id order. Every
+/// way of building a database writes it through this, so the rows look the same.
+pub struct SimilaritiesCsv(csv::Writer<File>);
+
+impl SimilaritiesCsv {
+    pub fn create(path: &str) -> SimilaritiesCsv {
+        let file = File::create(path).expect("Failed to create file");
+        let mut writer = csv::Writer::from_writer(file);
         writer
-            .write_record(&[
-                doc1_id.to_string(),
-                fmt::format(format_args!("{:?}", similar_docs)),
-            ])
+            .write_record(["document_id", "similar_documents"])
+            .expect("Failed to write header");
+        SimilaritiesCsv(writer)
+    }
+
+    pub fn write(&mut self, doc_id: u32, similar: impl IntoIterator<Item = (u32, PairSimilarity)>) {
+        let similar: BTreeMap<u32, PairSimilarity> = similar.into_iter().collect();
+        self.0
+            .write_record(&[doc_id.to_string(), format!("{:?}", similar)])
             .expect("Failed to write record");
     }
 
-    writer.flush().expect("Failed to flush writer");
+    pub fn finish(mut self) {
+        self.0.flush().expect("Failed to flush writer");
+    }
 }
 
-pub fn load_data(file_path: &Path) -> HashMap<u32, Record> {
-    let file = File::open(file_path).expect("Failed to open file");
-
-    let mut csv_reader = csv::ReaderBuilder::new().delimiter(b';').from_reader(file);
+/// Loads every record of an input, along with the labels they use.
+pub fn load_data(file_path: &Path, options: &LoadOptions) -> (HashMap<u32, Record>, LabelSet) {
+    let mut loader = RecordLoader::open(file_path, options);
 
     let mut final_data: HashMap<u32, Record> = HashMap::new();
-    for result in csv_reader.deserialize() {
-        let record: Record = result.expect("Record didn't map correctly");
+    let loaded: Result<(), LoadError> = loader.by_ref().try_for_each(|record| {
+        let record = record?;
+        // the loader only lets a repeated id through with `--on-duplicate-id last`
         final_data.insert(record.id, record);
+        Ok(())
+    });
+    loader.summary.loaded = final_data.len();
+    loader.summary.print(options);
+    if let Err(e) = loaded {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
     println!("total records: {}", final_data.len());
-    final_data
+    (final_data, loader.labels)
 }
 
 pub fn tokenize(text: &String) -> HashSet<String> {
@@ -323,50 +706,56 @@ pub fn tokenize(text: &String) -> HashSet<String> {
 }
 
 pub fn build_inverted_index(records: &HashMap<u32, Record>) -> InverseIndexDB {
-    let mut inverted_index: InverseIndexDB = HashMap::new();
-
-    for record in records {
-        let record = record.1;
-        let combined_string = vec![record.text.as_str(), record.title.as_str()].join(" ");
-        let tokenized_text = tokenize(&combined_string);
+    records
+        .par_iter()
+        .fold(
+            HashMap::new,
+            |mut inverted_index: InverseIndexDB, (_, record)| {
+                let combined_string = vec![record.text.as_str(), record.title.as_str()].join(" ");
+                let tokenized_text = tokenize(&combined_string);
+
+                for token in tokenized_text {
+                    inverted_index.entry(token).or_default().insert(record.id);
+                }
 
-        for token in tokenized_text {
-            if inverted_index.contains_key(&token) {
-                inverted_index.get_mut(&token).unwrap().insert(record.id);
-            } else {
-                // println!("Created key {:?}", &token);
-                inverted_index.insert(token, HashSet::from([record.id]));
+                inverted_index
+            },
+        )
+        .reduce(HashMap::new, |mut merged, partial| {
+            for (token, ids) in partial {
+                merged.entry(token).or_default().extend(ids);
             }
-        }
-    }
-
-    inverted_index
+            merged
+        })
 }
 
-pub fn create_shingles(documents: &Vec<String>, k: usize) -> HashMap<u32, HashSet<String>> {
-    let mut shingled_dataset: HashMap<u32, HashSet<String>> = HashMap::new();
-
-    for document_id in 0..documents.len() {
-        let document = documents.get(document_id).unwrap();
-        let mut document_shingles: HashSet<String> = HashSet::new();
+pub fn create_shingles(
+    documents: &HashMap<u32, String>,
+    k: usize,
+) -> HashMap<u32, HashSet<String>> {
+    documents
+        .par_iter()
+        .map(|(document_id, document)| {
+            let mut document_shingles: HashSet<String> = HashSet::new();
 
-        for i in 0..(document.len() - k + 1) {
-            // println!("{:?}, {:?}, {:?}", document_id, document.len(), i..(i + k));
+            for i in 0..(document.len() - k + 1) {
+                // println!("{:?}, {:?}, {:?}", document_id, document.len(), i..(i + k));
 
-            if let Some(shingle) = document.get(i..(i + k)) {
-                document_shingles.insert(shingle.to_string());
+                if let Some(shingle) = document.get(i..(i + k)) {
+                    document_shingles.insert(shingle.to_string());
+                }
             }
-        }
 
-        shingled_dataset.insert(document_id as u32, document_shingles);
-    }
-
-    shingled_dataset
+            (*document_id, document_shingles)
+        })
+        .collect()
 }
 
-fn create_hash_func() -> Box<dyn Fn(&str) -> u64> {
+/// Hash functions are shared between worker threads.
+pub type HashFunc = Box<dyn Fn(&str) -> u64 + Send + Sync>;
+
+fn create_hash_func(rng: &mut impl Rng) -> HashFunc {
     // Generate random coefficients `a` and `b`
-    let mut rng = rand::thread_rng();
     let a: u64 = rng.gen_range(10..10_000);
     let b: u64 = rng.gen_range(10..10_000);
     let large_prime: u64 = 95_633;
@@ -378,15 +767,12 @@ fn create_hash_func() -> Box<dyn Fn(&str) -> u64> {
     })
 }
 
-fn generate_hash_funcs(k: usize) -> Vec<Box<dyn Fn(&str) -> u64>> {
+fn generate_hash_funcs(k: usize, rng: &mut impl Rng) -> Vec<HashFunc> {
     // Generate `k` hash functions
-    (0..k).map(|_| create_hash_func()).collect()
+    (0..k).map(|_| create_hash_func(rng)).collect()
 }
 
-fn generate_minhash_signature(
-    data: &HashSet<String>,
-    hash_funcs: &Vec<Box<dyn Fn(&str) -> u64>>,
-) -> Vec<u64> {
+fn generate_minhash_signature(data: &HashSet<String>, hash_funcs: &Vec<HashFunc>) -> Vec<u64> {
     let mut minhash_signature: Vec<u64> = Vec::new();
 
     for hash_func in hash_funcs {
@@ -413,3 +799,72 @@ pub fn minhash_similarity(a: &Vec<u64>, b: &Vec<u64>) -> f64 {
     let matches = a.iter().zip(b).filter(|&(x, y)| x == y).count();
     matches as f64 / a.len() as f64
 }
+
+/// 64 bit FNV-1a of `bytes`. Hashes stored in a database or handed to clients use this rather
+/// than `DefaultHasher`, whose output may change with the Rust version.
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::{corpus, write_input, ScratchDir};
+
+    const RECORDS: u32 = 48;
+
+    /// Runs `process` on the corpus with `threads` workers and returns the similarities csv.
+    fn similarities_csv(extra: &[&str], threads: usize) -> String {
+        let dir = ScratchDir::new(&format!("threads-{}-{}", extra.join(""), threads));
+        let (input, db, csv) = (
+            dir.join("input.csv"),
+            dir.join("records.db"),
+            dir.join("similarities.csv"),
+        );
+        write_input(&input, &corpus(RECORDS, 3));
+
+        let mut args = vec![
+            "process".to_string(),
+            format!("--input={}", input.display()),
+            format!("--db={}", db.display()),
+            format!("--similarities-csv={}", csv.display()),
+            "--seed=7".to_string(),
+            "--min-similarity=0.5".to_string(),
+        ];
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        let args = Args::parse(args);
+
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| run_processing(&args));
+
+        std::fs::read_to_string(&csv).unwrap()
+    }
+
+    #[test]
+    fn similarities_do_not_depend_on_the_thread_count() {
+        for extra in [
+            &["--algorithm=minhash"][..],
+            &["--algorithm=minhash", "--streaming"],
+            &["--algorithm=simhash"],
+            &["--algorithm=weighted-minhash", "--weighting=tfidf"],
+        ] {
+            let single = similarities_csv(extra, 1);
+            // more pairs than each record with itself, so there is something to compare
+            assert!(
+                single.matches("reverse_containment").count() > RECORDS as usize,
+                "{:?} found no similar pairs:\n{}",
+                extra,
+                single
+            );
+            assert_eq!(single, similarities_csv(extra, 4), "{:?}", extra);
+        }
+    }
+}
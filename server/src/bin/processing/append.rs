@@ -0,0 +1,334 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use super::{
+    cli::{self, Args},
+    ingest::{self, IngestSettings, Ingestor},
+    labels::{LabelSet, SaveError},
+    loader::{LoadOptions, OnDuplicateId, RecordLoader},
+    metadata::{self, Metadata, MetadataValue},
+    normalize::Normalization,
+    seed_from_args, Record, SimilarityMetric, DEFAULT_DB_FILE, DEFAULT_SIMILARITY_THRESHOLD,
+};
+
+/// Settings for a database that doesn't record its own: the defaults of `process`, with the
+/// normalization it was built with.
+pub fn default_settings(conn: &Connection, seed: u64) -> rusqlite::Result<IngestSettings> {
+    Ok(IngestSettings {
+        shingle_size: 3,
+        minhash_length: 20,
+        bands: 5,
+        seed,
+        metric: SimilarityMetric::Jaccard,
+        threshold: DEFAULT_SIMILARITY_THRESHOLD,
+        normalization: Normalization::load(conn)?,
+    })
+}
+
+/// `default_settings` with the `--bands`, `--metric`, `--min-similarity` and `--seed` given on
+/// the command line. Only used if the database doesn't record how it was built.
+pub fn fallback_settings(conn: &Connection, args: &Args) -> rusqlite::Result<IngestSettings> {
+    let mut fallback = default_settings(conn, seed_from_args(args))?;
+    fallback.bands = args.parse_or("bands", fallback.bands);
+    fallback.metric = args.parse_or("metric", fallback.metric);
+    fallback.threshold = args.parse_or("min-similarity", fallback.threshold);
+    Ok(fallback)
+}
+
+/// An ingestor over an existing database, for the command line.
+///
+/// Databases the ingestor didn't build have no signatures or LSH buckets yet, their records are
+/// indexed first with `fallback` settings (unless the database recorded its own).
+pub fn index_database(
+    conn: &Connection,
+    fallback: IngestSettings,
+) -> rusqlite::Result<Ingestor<'_>> {
+    let settings = IngestSettings::load(conn)?.unwrap_or(fallback);
+    let ingestor = Ingestor::new(conn, settings)?;
+    if !ingest::is_indexed(conn)? {
+        ingestor.index_existing(10_000)?;
+    }
+    Ok(ingestor)
+}
+
+/// An ingestor over an existing database, for the server. Indexing a whole corpus takes far
+/// longer than a request may, so a database that isn't indexed yet is an error.
+pub fn open_ingestor(conn: &Connection) -> Result<Ingestor<'_>, AppendError> {
+    if !ingest::is_indexed(conn)? {
+        return Err(AppendError::NotIndexed);
+    }
+    let settings = IngestSettings::load(conn)?.ok_or(AppendError::NotIndexed)?;
+    Ok(Ingestor::new(conn, settings)?)
+}
+
+/// Smallest id above every record in the database.
+pub fn next_id(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("SELECT COALESCE(MAX(id) + 1, 1) FROM records", [], |row| {
+        row.get(0)
+    })
+}
+
+/// A record sent to `POST /records`.
+#[derive(Debug, Deserialize)]
+pub struct NewRecord {
+    /// taken from the database when left out
+    pub id: Option<u32>,
+    pub title: String,
+    pub text: String,
+    /// label name or id
+    pub label: serde_json::Value,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct AppendReport {
+    pub inserted: Vec<u32>,
+    /// ids that were already taken
+    pub skipped: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub enum AppendError {
+    /// A record that can't be stored, with the reason.
+    Invalid(String),
+    /// The database has to be indexed with `processing index` first.
+    NotIndexed,
+    Database(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for AppendError {
+    fn from(e: rusqlite::Error) -> Self {
+        AppendError::Database(e)
+    }
+}
+
+impl From<SaveError> for AppendError {
+    fn from(e: SaveError) -> Self {
+        match e {
+            SaveError::Conflict(reason) => AppendError::Invalid(reason),
+            SaveError::Database(e) => AppendError::Database(e),
+        }
+    }
+}
+
+impl std::fmt::Display for AppendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppendError::Invalid(reason) => f.write_str(reason),
+            AppendError::NotIndexed => f.write_str(NOT_INDEXED),
+            AppendError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppendError {}
+
+pub const NOT_INDEXED: &str =
+    "the database isn't indexed for edits yet, run `processing index` on it first";
+
+/// Raw text of a json value, strings without their quotes.
+fn json_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Checks `new` against the database's labels and metadata fields and turns it into records.
+/// Nothing is written.
+pub fn to_records(
+    conn: &Connection,
+    new: Vec<NewRecord>,
+    labels: &mut LabelSet,
+) -> Result<Vec<Record>, AppendError> {
+    let fields = metadata::fields(conn)?;
+    let mut next_id = next_id(conn)?;
+
+    new.into_iter()
+        .enumerate()
+        .map(|(position, new)| {
+            let invalid =
+                |reason: String| AppendError::Invalid(format!("record {}: {}", position, reason));
+
+            let (label, label_name) = labels.resolve(&json_text(&new.label)).map_err(invalid)?;
+            let mut record_metadata = Metadata::new();
+            for (field, value) in &new.metadata {
+                if value.is_null() {
+                    continue;
+                }
+                metadata::check_field_name(field).map_err(invalid)?;
+                let value = match fields.get(field) {
+                    // declared fields keep their type
+                    Some(kind) => kind
+                        .parse(&json_text(value))
+                        .map_err(|e| invalid(format!("metadata `{}`: {}", field, e)))?,
+                    None => MetadataValue::from_json(value).ok_or_else(|| {
+                        invalid(format!("metadata `{}` must be a plain value", field))
+                    })?,
+                };
+                record_metadata.insert(field.clone(), value);
+            }
+
+            let id = new.id.unwrap_or_else(|| {
+                next_id += 1;
+                next_id - 1
+            });
+            Ok(Record {
+                id,
+                title: new.title,
+                text: new.text,
+                label,
+                label_name: Some(label_name),
+                metadata: record_metadata,
+            })
+        })
+        .collect()
+}
+
+/// Adds records to a served database and makes them searchable right away: they are indexed,
+/// compared with the existing corpus, and the `inverse_index` / `similarities` rows they touch
+/// are rebuilt. It all happens in one transaction, a request that fails leaves the database as
+/// it was.
+pub fn append_records(conn: &Connection, new: Vec<NewRecord>) -> Result<AppendReport, AppendError> {
+    let tx = conn.unchecked_transaction()?;
+    let mut labels = LabelSet::load(conn)?;
+    let records = to_records(conn, new, &mut labels)?;
+
+    let ingestor = open_ingestor(conn)?;
+    let ingested = ingestor.ingest(&records)?;
+    labels.save(conn)?;
+    ingestor.materialize(Some(&ingested.touched))?;
+    tx.commit()?;
+
+    Ok(AppendReport {
+        inserted: ingested.inserted,
+        skipped: ingested.skipped,
+    })
+}
+
+/// `append`: adds the records of an input file to an existing database.
+pub fn run_append(args: &Args) {
+    let Some(input_file) = args.get("input") else {
+        cli::exit_with_usage("append needs --input");
+    };
+    let db_file = args.get_or("db", DEFAULT_DB_FILE);
+    let chunk_size = args.parse_or("chunk-size", 10_000usize).max(1);
+    let load_options = LoadOptions::from_args(args);
+    if load_options.on_duplicate_id == OnDuplicateId::Last {
+        cli::exit_with_usage("append can't keep the last of several rows with the same id");
+    }
+
+    let conn = Connection::open(&db_file).expect("Failed to open database");
+
+    println!("Preparing the database for appends...");
+    let fallback = fallback_settings(&conn, args).expect("Failed to read database");
+    let ingestor = index_database(&conn, fallback).expect("Failed to prepare database");
+
+    let mut loader = RecordLoader::open(Path::new(input_file), &load_options)
+        .starting_at_id(next_id(&conn).expect("Failed to read database"))
+        .tracking_ids_on_disk();
+    if load_options.input.labels.is_none() {
+        loader.labels = LabelSet::load(&conn).expect("Failed to read labels");
+    } else if let Err(e) = loader.labels.save(&conn) {
+        // checked before any record is stored under an id that means something else
+        eprintln!("--labels: {}", e);
+        std::process::exit(1);
+    }
+
+    let (mut inserted, mut skipped) = (0, 0);
+    // chunks before a row that stops the run are already committed
+    let mut failure = None;
+    loop {
+        let chunk = match loader.take_chunk(chunk_size) {
+            Ok(chunk) if chunk.is_empty() => break,
+            Ok(chunk) => chunk,
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        };
+
+        // rebuilt chunk by chunk, so the touched rows never cover the whole input
+        let tx = conn
+            .unchecked_transaction()
+            .expect("Failed to begin transaction");
+        let ingested = ingestor.ingest(&chunk).expect("Failed to ingest chunk");
+        loader.labels.save(&conn).expect("Failed to store labels");
+        ingestor
+            .materialize(Some(&ingested.touched))
+            .expect("Failed to update tables");
+        tx.commit().expect("Failed to commit chunk");
+
+        for id in &ingested.skipped {
+            println!("Skipping record {}: id already exists", id);
+        }
+        inserted += ingested.inserted.len();
+        skipped += ingested.skipped.len();
+    }
+    loader.summary.print(&load_options);
+    println!("appended {} records ({} skipped)", inserted, skipped);
+    if let Some(e) = failure {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// `index`: indexes the records of an existing database, so the server can add, edit and delete
+/// records in it.
+pub fn run_index(args: &Args) {
+    let db_file = args.get_or("db", DEFAULT_DB_FILE);
+    let conn = Connection::open(&db_file).expect("Failed to open database");
+    if ingest::is_indexed(&conn).expect("Failed to read database") {
+        println!("{} is already indexed", db_file);
+        return;
+    }
+
+    println!("Indexing the records...");
+    let fallback = fallback_settings(&conn, args).expect("Failed to read database");
+    index_database(&conn, fallback).expect("Failed to index database");
+    println!("{} is indexed", db_file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{corpus, fail_materialize, indexed_database, table_rows};
+    use super::*;
+
+    const TABLES: [&str; 7] = [
+        "records",
+        "signatures",
+        "lsh_buckets",
+        "similarity_edges",
+        "postings",
+        "inverse_index",
+        "labels",
+    ];
+
+    #[test]
+    fn a_failed_append_leaves_the_database_as_it_was() {
+        let conn = indexed_database(&corpus(12, 3));
+        fail_materialize(&conn);
+        let before: Vec<_> = TABLES
+            .iter()
+            .map(|table| table_rows(&conn, table))
+            .collect();
+
+        let new = NewRecord {
+            id: None,
+            title: "a new record".to_string(),
+            text: "with words nobody used before".to_string(),
+            label: serde_json::json!(1),
+            metadata: BTreeMap::new(),
+        };
+        assert!(append_records(&conn, vec![new]).is_err());
+
+        let after: Vec<_> = TABLES
+            .iter()
+            .map(|table| table_rows(&conn, table))
+            .collect();
+        assert_eq!(before, after);
+        assert!(conn.is_autocommit());
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+pub const USAGE: &str = "\
+usage: processing [command] [options]
+
+commands:
+  process              build processed.db from a csv file (default)
+  label-conflicts      list near-duplicates whose labels disagree
+  leakage              find near-duplicates between a train and a test csv
+  index                index an existing database so the server can add, edit and delete
+                       its records
+  append               add the records of --input to an existing database
+  update               replace the records of an existing database with the rows of --input
+  delete               remove the records listed in --ids from an existing database
+
+options:
+  --input <path>             csv / jsonl file or text directory to process
+  --format <name>            input format: csv, jsonl or text (default: from the path)
+  --delimiter <char>         csv delimiter, `tab` for tabs (default: ;)
+  --no-headers               the csv has no header row, columns are numbered from 0
+  --columns <mapping>        record fields to source columns, e.g. id=doc_id,text=body
+  --generate-ids             number records from 1 instead of reading an id column
+  --labels <names>           accepted labels, e.g. neg,pos or 0=neg,1=pos (default: any)
+  --default-label <label>    label for rows without one, by name or id
+  --metadata <fields>        columns kept as metadata with their types, e.g.
+                             author,year:int,published:date (default: all others, as text)
+  --db <path>                sqlite database to write / read (default: processed.db)
+  --similarities-csv <path>  where to write the similarities csv
+  --min-similarity <f64>     similarity threshold
+  --algorithm <name>         near-duplicate algorithm: minhash (default), simhash or
+                             weighted-minhash
+  --weighting <name>         token weights for weighted-minhash: tf (default) or tfidf
+  --threads <n>              worker threads (default: all cores)
+  --seed <n>                 seed for the random hash functions (default: random)
+  --streaming                read and index the input in chunks instead of all at once
+  --chunk-size <n>           records per chunk with --streaming, append or update
+                             (default: 10000)
+  --bands <n>                maximum number of lsh bands (default: 5)
+  --on-error <policy>        rows that can't be read: fail (default), skip or quarantine
+  --on-duplicate-id <policy> rows repeating an id: first (default), last, fail or quarantine
+  --rejects <path>           where quarantined rows are written (default: rejects.csv)
+  --normalize <steps>        text cleanup before indexing: none (default), all, or any of
+                             html,entities,nfkc,punctuation,emails,urls,whitespace
+  --metric <name>            measure the threshold applies to: jaccard (default) or containment
+  --ids <ids>                comma separated record ids for delete
+  --json                     print reports as json
+
+leakage options:
+  --train <path>             input to index
+  --test <path>              input to probe the index with
+  --shingle-size <n>         characters per shingle (default: 3)
+  --minhash-length <n>       hash functions per signature (default: 20)
+  --output <path>            also write the leaked pairs to a csv file";
+
+/// Very small argument parser for the processing binary.
+///
+/// The first argument that does not start with `--` is the command, anything after it is
+/// either a `--flag value` / `--flag=value` pair, a bare `--switch`, or a positional value.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub command: Option<String>,
+    pub positional: Vec<String>,
+    flags: HashMap<String, String>,
+    switches: HashSet<String>,
+}
+
+impl Args {
+    pub fn from_env() -> Args {
+        Args::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Args {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            if let Some(flag) = arg.strip_prefix("--") {
+                if let Some((name, value)) = flag.split_once('=') {
+                    parsed.flags.insert(name.to_string(), value.to_string());
+                } else if args.peek().is_some_and(|next| !next.starts_with("--")) {
+                    parsed
+                        .flags
+                        .insert(flag.to_string(), args.next().unwrap_or_default());
+                } else {
+                    parsed.switches.insert(flag.to_string());
+                }
+            } else if parsed.command.is_none() && parsed.positional.is_empty() {
+                parsed.command = Some(arg);
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+
+        parsed
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).map(String::as_str)
+    }
+
+    pub fn get_or(&self, name: &str, default: &str) -> String {
+        self.get(name).unwrap_or(default).to_string()
+    }
+
+    /// Parses the value of `--name`, exiting with the usage text if it is malformed.
+    pub fn parse_or<T: FromStr>(&self, name: &str, default: T) -> T {
+        match self.get(name) {
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                exit_with_usage(&format!("invalid value `{}` for --{}", value, name))
+            }),
+            None => default,
+        }
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.switches.contains(name) || self.flags.contains_key(name)
+    }
+}
+
+pub fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    std::process::exit(1)
+}
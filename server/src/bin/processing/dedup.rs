@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use super::{stable_hash, PairSimilarity, Record, SimilaritiesDB};
+
+/// Groups of byte-identical records (after normalization), keyed by the representative id.
+/// Every group lists all of its members, the representative included, in ascending order.
+pub type DuplicateGroups = HashMap<u32, Vec<u32>>;
+
+/// Title and text lowercased with all whitespace runs collapsed to a single space. Quotes are
+/// replaced the same way `Record::to_db_string` does, so a record read back from the database
+/// normalizes to the same content.
+pub fn normalized_content(record: &Record) -> String {
+    format!("{} {}", record.title, record.text)
+        .replace('"', "”")
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Stored in `content_hashes`, so it has to stay the same across builds.
+pub fn content_hash(content: &str) -> u64 {
+    stable_hash(content.as_bytes())
+}
+
+/// Finds records whose normalized title + text are identical.
+///
+/// Records are bucketed by a hash of their normalized content and the content is compared
+/// within a bucket, so a hash collision never merges two different documents. Only groups with
+/// more than one member are returned and the smallest id of each group is its representative.
+pub fn find_exact_duplicates(records: &HashMap<u32, Record>) -> DuplicateGroups {
+    let mut buckets: HashMap<u64, Vec<(String, Vec<u32>)>> = HashMap::new();
+
+    let mut ids: Vec<&u32> = records.keys().collect();
+    ids.sort();
+
+    for id in ids {
+        let content = normalized_content(&records[id]);
+
+        let bucket = buckets.entry(content_hash(&content)).or_default();
+        match bucket.iter_mut().find(|(existing, _)| *existing == content) {
+            Some((_, members)) => members.push(*id),
+            None => bucket.push((content, vec![*id])),
+        }
+    }
+
+    buckets
+        .into_values()
+        .flatten()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(_, members)| (members[0], members))
+        .collect()
+}
+
+/// Adds the exact duplicates left out of the MinHash stage back into `similarities`.
+///
+/// Each duplicate gets a copy of its representative's row, and every row pointing at a
+/// representative also points at the rest of its group. Members of a group are similar to each
+/// other with similarity and containment 1.0.
+pub fn expand_exact_duplicates(similarities: &mut SimilaritiesDB, groups: &DuplicateGroups) {
+    let identical = PairSimilarity {
+        similarity: 1.0,
+        containment: 1.0,
+        reverse_containment: 1.0,
+    };
+
+    for representative in groups.keys() {
+        let row = similarities.entry(*representative).or_default();
+        row.insert(*representative, identical);
+    }
+
+    for row in similarities.values_mut() {
+        let duplicates: Vec<(u32, PairSimilarity)> = row
+            .iter()
+            .filter_map(|(id, pair)| groups.get(id).map(|members| (members, *pair)))
+            .flat_map(|(members, pair)| members.iter().map(move |member| (*member, pair)))
+            .collect();
+        row.extend(duplicates);
+    }
+
+    for (representative, members) in groups {
+        let row = similarities[representative].clone();
+        for member in members.iter().filter(|member| *member != representative) {
+            similarities.insert(*member, row.clone());
+        }
+    }
+}
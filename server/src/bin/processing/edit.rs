@@ -0,0 +1,276 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+
+use super::{
+    append::{self, fallback_settings, index_database, open_ingestor, AppendError, NewRecord},
+    cli::{self, Args},
+    labels::{LabelSet, SaveError},
+    loader::{LoadOptions, OnDuplicateId, RecordLoader},
+    Record, DEFAULT_DB_FILE,
+};
+
+#[derive(Debug)]
+pub enum EditError {
+    /// A record that can't be stored, with the reason.
+    Invalid(String),
+    NotFound(u32),
+    /// The database has to be indexed with `processing index` first.
+    NotIndexed,
+    Database(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for EditError {
+    fn from(e: rusqlite::Error) -> Self {
+        EditError::Database(e)
+    }
+}
+
+impl From<SaveError> for EditError {
+    fn from(e: SaveError) -> Self {
+        AppendError::from(e).into()
+    }
+}
+
+impl From<AppendError> for EditError {
+    fn from(e: AppendError) -> Self {
+        match e {
+            AppendError::Invalid(reason) => EditError::Invalid(reason),
+            AppendError::NotIndexed => EditError::NotIndexed,
+            AppendError::Database(e) => EditError::Database(e),
+        }
+    }
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::Invalid(reason) => f.write_str(reason),
+            EditError::NotFound(id) => write!(f, "record {} does not exist", id),
+            EditError::NotIndexed => f.write_str(append::NOT_INDEXED),
+            EditError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+fn exists(conn: &Connection, id: u32) -> rusqlite::Result<bool> {
+    Ok(conn
+        .query_row("SELECT 1 FROM records WHERE id = (?1)", params![id], |_| {
+            Ok(())
+        })
+        .optional()?
+        .is_some())
+}
+
+/// Replaces record `id` of a served database with `new` and re-indexes it, along with the
+/// `inverse_index` / `similarities` rows of its old and new tokens and neighbours. It all happens
+/// in one transaction, a request that fails leaves the database as it was.
+pub fn update_record(conn: &Connection, id: u32, mut new: NewRecord) -> Result<Record, EditError> {
+    if new.id.is_some_and(|body_id| body_id != id) {
+        return Err(EditError::Invalid(format!(
+            "record id {} doesn't match the path id {}",
+            new.id.unwrap_or_default(),
+            id
+        )));
+    }
+    if !exists(conn, id)? {
+        return Err(EditError::NotFound(id));
+    }
+    new.id = Some(id);
+
+    let tx = conn.unchecked_transaction()?;
+    let mut labels = LabelSet::load(conn)?;
+    let mut records = append::to_records(conn, vec![new], &mut labels)?;
+
+    let ingestor = open_ingestor(conn)?;
+    let edited = ingestor.update(&records)?;
+    labels.save(conn)?;
+    ingestor.materialize(Some(&edited.touched))?;
+    tx.commit()?;
+
+    Ok(records.remove(0))
+}
+
+/// Deletes record `id` of a served database and everything derived from it, in one transaction.
+pub fn delete_record(conn: &Connection, id: u32) -> Result<(), EditError> {
+    if !exists(conn, id)? {
+        return Err(EditError::NotFound(id));
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    let ingestor = open_ingestor(conn)?;
+    let edited = ingestor.delete(&[id])?;
+    ingestor.materialize(Some(&edited.touched))?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// `update`: replaces the records of an existing database with the rows of an input file that
+/// have the same id.
+pub fn run_update(args: &Args) {
+    let Some(input_file) = args.get("input") else {
+        cli::exit_with_usage("update needs --input");
+    };
+    let db_file = args.get_or("db", DEFAULT_DB_FILE);
+    let chunk_size = args.parse_or("chunk-size", 10_000usize).max(1);
+    let load_options = LoadOptions::from_args(args);
+    if load_options.input.generate_ids {
+        cli::exit_with_usage("update matches records by id, it can't use --generate-ids");
+    }
+    if load_options.on_duplicate_id == OnDuplicateId::Last {
+        cli::exit_with_usage("update can't keep the last of several rows with the same id");
+    }
+
+    let conn = Connection::open(&db_file).expect("Failed to open database");
+
+    println!("Preparing the database for updates...");
+    let fallback = fallback_settings(&conn, args).expect("Failed to read database");
+    let ingestor = index_database(&conn, fallback).expect("Failed to prepare database");
+
+    let mut loader =
+        RecordLoader::open(Path::new(input_file), &load_options).tracking_ids_on_disk();
+    if load_options.input.labels.is_none() {
+        loader.labels = LabelSet::load(&conn).expect("Failed to read labels");
+    } else if let Err(e) = loader.labels.save(&conn) {
+        // checked before any record is stored under an id that means something else
+        eprintln!("--labels: {}", e);
+        std::process::exit(1);
+    }
+
+    let (mut updated, mut missing) = (0, 0);
+    // chunks before a row that stops the run are already committed
+    let mut failure = None;
+    loop {
+        let chunk = match loader.take_chunk(chunk_size) {
+            Ok(chunk) if chunk.is_empty() => break,
+            Ok(chunk) => chunk,
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        };
+
+        // rebuilt chunk by chunk, so the touched rows never cover the whole input
+        let tx = conn
+            .unchecked_transaction()
+            .expect("Failed to begin transaction");
+        let edited = ingestor.update(&chunk).expect("Failed to update chunk");
+        loader.labels.save(&conn).expect("Failed to store labels");
+        ingestor
+            .materialize(Some(&edited.touched))
+            .expect("Failed to update tables");
+        tx.commit().expect("Failed to commit chunk");
+
+        for id in &edited.missing {
+            println!("Skipping record {}: not in the database", id);
+        }
+        updated += edited.edited.len();
+        missing += edited.missing.len();
+    }
+    loader.summary.print(&load_options);
+    println!("updated {} records ({} missing)", updated, missing);
+    if let Some(e) = failure {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// `delete`: removes the records listed in `--ids` from an existing database.
+pub fn run_delete(args: &Args) {
+    let Some(ids) = args.get("ids") else {
+        cli::exit_with_usage("delete needs --ids");
+    };
+    let ids: Vec<u32> = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse().unwrap_or_else(|_| {
+                cli::exit_with_usage(&format!("--ids: `{}` is not a record id", id))
+            })
+        })
+        .collect();
+    let db_file = args.get_or("db", DEFAULT_DB_FILE);
+
+    let conn = Connection::open(&db_file).expect("Failed to open database");
+    let fallback = fallback_settings(&conn, args).expect("Failed to read database");
+    let ingestor = index_database(&conn, fallback).expect("Failed to prepare database");
+
+    let tx = conn
+        .unchecked_transaction()
+        .expect("Failed to begin transaction");
+    let edited = ingestor.delete(&ids).expect("Failed to delete records");
+    ingestor
+        .materialize(Some(&edited.touched))
+        .expect("Failed to update tables");
+    tx.commit().expect("Failed to commit deletes");
+    for id in &edited.missing {
+        println!("Skipping record {}: not in the database", id);
+    }
+    println!(
+        "deleted {} records ({} missing), {} documents and {} tokens updated",
+        edited.edited.len(),
+        edited.missing.len(),
+        edited.touched.documents.len(),
+        edited.touched.tokens.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{corpus, fail_materialize, indexed_database, table_rows};
+    use super::*;
+
+    const TABLES: [&str; 8] = [
+        "records",
+        "record_metadata",
+        "signatures",
+        "lsh_buckets",
+        "content_hashes",
+        "similarity_edges",
+        "postings",
+        "inverse_index",
+    ];
+
+    fn snapshot(conn: &Connection) -> Vec<Vec<Vec<rusqlite::types::Value>>> {
+        TABLES.iter().map(|table| table_rows(conn, table)).collect()
+    }
+
+    #[test]
+    fn a_failed_update_leaves_the_database_as_it_was() {
+        let conn = indexed_database(&corpus(12, 5));
+        fail_materialize(&conn);
+        let before = snapshot(&conn);
+
+        let new = NewRecord {
+            id: None,
+            title: "an edited title".to_string(),
+            text: "and a text that has nothing in common with the old one".to_string(),
+            label: serde_json::json!(0),
+            metadata: Default::default(),
+        };
+        assert!(matches!(
+            update_record(&conn, 2, new),
+            Err(EditError::Database(_))
+        ));
+
+        assert_eq!(before, snapshot(&conn));
+        assert!(conn.is_autocommit());
+    }
+
+    #[test]
+    fn a_failed_delete_leaves_the_database_as_it_was() {
+        let conn = indexed_database(&corpus(12, 5));
+        fail_materialize(&conn);
+        let before = snapshot(&conn);
+
+        assert!(matches!(
+            delete_record(&conn, 2),
+            Err(EditError::Database(_))
+        ));
+
+        assert_eq!(before, snapshot(&conn));
+        assert!(conn.is_autocommit());
+    }
+}
@@ -0,0 +1,184 @@
+use rayon::prelude::*;
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::{tokenize, InverseIndexDB, Record};
+
+/// A record field that is indexed on its own, so queries can be limited to it and matches in it
+/// can weigh more than matches elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Field {
+    Title,
+    Text,
+}
+
+impl Field {
+    pub const ALL: [Field; 2] = [Field::Title, Field::Text];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Text => "text",
+        }
+    }
+
+    pub fn value(self, record: &Record) -> &str {
+        match self {
+            Field::Title => &record.title,
+            Field::Text => &record.text,
+        }
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "title" => Ok(Field::Title),
+            "text" => Ok(Field::Text),
+            _ => Err(format!("unknown field `{}`, expected title or text", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The tokens of each field of an (already normalized) record, escaped like the inverse index.
+pub fn field_tokens(record: &Record) -> Vec<(Field, Vec<String>)> {
+    Field::ALL
+        .iter()
+        .map(|field| {
+            let mut tokens: Vec<String> = tokenize(&field.value(record).to_string())
+                .into_iter()
+                .map(|token| token.replace('"', "”"))
+                .collect();
+            tokens.sort();
+            (*field, tokens)
+        })
+        .collect()
+}
+
+/// Per-field counterpart of `build_inverted_index`.
+pub fn build_field_index(records: &HashMap<u32, Record>) -> HashMap<Field, InverseIndexDB> {
+    records
+        .par_iter()
+        .fold(
+            HashMap::new,
+            |mut index: HashMap<Field, InverseIndexDB>, (_, record)| {
+                for (field, tokens) in field_tokens(record) {
+                    let field_index = index.entry(field).or_default();
+                    for token in tokens {
+                        field_index.entry(token).or_default().insert(record.id);
+                    }
+                }
+                index
+            },
+        )
+        .reduce(HashMap::new, |mut merged, partial| {
+            for (field, tokens) in partial {
+                let field_index = merged.entry(field).or_default();
+                for (token, ids) in tokens {
+                    field_index.entry(token).or_default().extend(ids);
+                }
+            }
+            merged
+        })
+}
+
+/// `field_index` is served to the api, one row per field and token with the same `entries`
+/// layout as `inverse_index`. `field_postings` is its source for databases the ingestor
+/// maintains.
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS field_index ( field TEXT NOT NULL, string TEXT NOT NULL, entries TEXT, PRIMARY KEY (field, string) );
+         CREATE TABLE IF NOT EXISTS field_postings ( field TEXT NOT NULL, token TEXT NOT NULL, doc_id INTEGER NOT NULL, PRIMARY KEY (field, token, doc_id) );
+         CREATE INDEX IF NOT EXISTS field_postings_token ON field_postings (token);
+         CREATE INDEX IF NOT EXISTS field_postings_doc_id ON field_postings (doc_id);",
+    )
+}
+
+pub fn table_exists(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = (?1))",
+        params![name],
+        |row| row.get(0),
+    )
+}
+
+/// Writes the field index of an in-memory build.
+pub fn save_index(
+    conn: &Connection,
+    index: &HashMap<Field, InverseIndexDB>,
+) -> rusqlite::Result<()> {
+    create_tables(conn)?;
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = conn.prepare("INSERT OR REPLACE INTO field_index VALUES (?1, ?2, ?3)")?;
+        for (field, tokens) in index.iter().collect::<BTreeMap<_, _>>() {
+            for (token, ids) in tokens.iter().collect::<BTreeMap<_, _>>() {
+                let entries = ids
+                    .iter()
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                stmt.execute(params![field.name(), token, format!("[{}]", entries)])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// Stores the field postings of (already normalized) records.
+pub fn insert_postings(conn: &Connection, records: &[Record]) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT OR IGNORE INTO field_postings (field, token, doc_id) VALUES (?1, ?2, ?3)",
+    )?;
+    for record in records {
+        for (field, tokens) in field_tokens(record) {
+            for token in tokens {
+                stmt.execute(params![field.name(), token, record.id])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+const FIELD_INDEX_ROWS: &str = "
+    SELECT field, token, '[' || group_concat(doc_id, ', ' ORDER BY doc_id) || ']'
+    FROM field_postings";
+
+/// Rebuilds the `field_index` rows from `field_postings`, either completely or only for
+/// `tokens`. Runs inside `ingest::materialize`'s transaction.
+pub fn materialize(conn: &Connection, tokens: Option<&BTreeSet<String>>) -> rusqlite::Result<()> {
+    match tokens {
+        None => {
+            conn.execute("DELETE FROM field_index", ())?;
+            conn.execute(
+                &format!(
+                    "INSERT INTO field_index {} GROUP BY field, token ORDER BY field, token",
+                    FIELD_INDEX_ROWS
+                ),
+                (),
+            )?;
+        }
+        Some(tokens) => {
+            let mut stmt_delete = conn.prepare("DELETE FROM field_index WHERE string = (?1)")?;
+            let mut stmt_insert = conn.prepare(&format!(
+                "INSERT INTO field_index {} WHERE token = (?1) GROUP BY field, token",
+                FIELD_INDEX_ROWS
+            ))?;
+            for token in tokens {
+                stmt_delete.execute(params![token])?;
+                stmt_insert.execute(params![token])?;
+            }
+        }
+    }
+    Ok(())
+}
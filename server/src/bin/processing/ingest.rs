@@ -0,0 +1,944 @@
+use rand::{rngs::StdRng, SeedableRng};
+use rayon::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
+    path::Path,
+};
+
+use super::{
+    build_table_creation_commands,
+    cli::{self, Args},
+    create_shingles, dedup, fields, generate_hash_funcs, generate_minhash_signature,
+    labels::LabelSet,
+    loader::{LoadOptions, OnDuplicateId, RecordLoader},
+    lsh::LshIndex,
+    metadata, minhash_similarity,
+    normalize::Normalization,
+    parse_similar_documents, tokenize, HashFunc, PairSimilarity, Record, SimilaritiesCsv,
+    SimilarityMetric,
+};
+
+/// A transaction of its own, or none if the caller already opened one on the connection. The
+/// ingestor's steps commit on their own when run alone, and when they are part of a larger
+/// change, like adding records through the server, they are rolled back with the rest of it.
+pub struct JoinedTransaction<'conn>(Option<Transaction<'conn>>);
+
+impl<'conn> JoinedTransaction<'conn> {
+    pub fn begin(conn: &'conn Connection) -> rusqlite::Result<JoinedTransaction<'conn>> {
+        if conn.is_autocommit() {
+            Ok(JoinedTransaction(Some(conn.unchecked_transaction()?)))
+        } else {
+            Ok(JoinedTransaction(None))
+        }
+    }
+
+    pub fn commit(self) -> rusqlite::Result<()> {
+        match self.0 {
+            Some(tx) => tx.commit(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parameters a database was built with. They are stored in the `metadata` table so that records
+/// added later are hashed and compared exactly like the ones already in it.
+#[derive(Debug, Clone)]
+pub struct IngestSettings {
+    pub shingle_size: usize,
+    pub minhash_length: usize,
+    pub bands: usize,
+    pub seed: u64,
+    pub metric: SimilarityMetric,
+    pub threshold: f64,
+    pub normalization: Normalization,
+}
+
+impl IngestSettings {
+    pub fn save(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS metadata ( key TEXT PRIMARY KEY, value TEXT )",
+            (),
+        )?;
+        let mut stmt = conn.prepare("INSERT OR REPLACE INTO metadata VALUES (?1, ?2)")?;
+        stmt.execute(params!["shingle_size", self.shingle_size.to_string()])?;
+        stmt.execute(params!["minhash_length", self.minhash_length.to_string()])?;
+        stmt.execute(params!["bands", self.bands.to_string()])?;
+        stmt.execute(params!["seed", self.seed.to_string()])?;
+        stmt.execute(params!["metric", self.metric.to_string()])?;
+        stmt.execute(params!["threshold", self.threshold.to_string()])?;
+        stmt.execute(params!["normalization", self.normalization.to_string()])?;
+        Ok(())
+    }
+
+    /// The settings stored in `conn`, or `None` if it was not built by the ingestor.
+    pub fn load(conn: &Connection) -> rusqlite::Result<Option<IngestSettings>> {
+        let has_metadata: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_metadata {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare("SELECT key, value FROM metadata")?;
+        let values: HashMap<String, String> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        fn get<T: std::str::FromStr>(values: &HashMap<String, String>, key: &str) -> Option<T> {
+            values.get(key)?.parse().ok()
+        }
+
+        Ok((|| {
+            Some(IngestSettings {
+                shingle_size: get(&values, "shingle_size")?,
+                minhash_length: get(&values, "minhash_length")?,
+                bands: get(&values, "bands")?,
+                seed: get(&values, "seed")?,
+                metric: get(&values, "metric")?,
+                threshold: get(&values, "threshold")?,
+                // databases from before normalization was recorded used none
+                normalization: get(&values, "normalization").unwrap_or_default(),
+            })
+        })())
+    }
+}
+
+/// Hash of the `content_hashes` and `lsh_buckets` rows, recorded as `hash_version` in the
+/// `metadata` table.
+const HASH_VERSION: &str = "fnv1a";
+
+/// Whether every record of the database is in the ingestor's tables, so records can be added,
+/// edited and deleted one at a time. Databases the ingestor built are, others have to go through
+/// `Ingestor::index_existing` first.
+pub fn is_indexed(conn: &Connection) -> rusqlite::Result<bool> {
+    if !fields::table_exists(conn, "metadata")? {
+        return Ok(false);
+    }
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM metadata WHERE key = 'indexed' AND value = 'true')",
+        [],
+        |row| row.get(0),
+    )
+}
+
+fn mark_indexed(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO metadata VALUES ('indexed', 'true')",
+        (),
+    )?;
+    Ok(())
+}
+
+/// Creates the tables served to the api along with the ones the ingestor keeps its state in:
+///
+/// - `postings`: one row per (token, document), `inverse_index` is built from it
+/// - `similarity_edges`: one row per similar pair and direction, `similarities` is built from it
+/// - `signatures` / `lsh_buckets`: MinHash signatures and their LSH bands for candidate search
+/// - `content_hashes`: normalized content hashes for exact duplicate detection
+/// - `field_postings`: `postings` per field, `field_index` is built from it
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    let (records_new_table, _) =
+        build_table_creation_commands("records", &vec!["id", "title", "text", "label"], &vec![]);
+    conn.execute(&records_new_table, ())?;
+
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS inverse_index ( string TEXT PRIMARY KEY, entries TEXT );
+         CREATE TABLE IF NOT EXISTS similarities ( document_id INTEGER PRIMARY KEY, similar_documents TEXT );
+         CREATE TABLE IF NOT EXISTS metadata ( key TEXT PRIMARY KEY, value TEXT );
+         CREATE TABLE IF NOT EXISTS postings ( token TEXT NOT NULL, doc_id INTEGER NOT NULL, PRIMARY KEY (token, doc_id) );
+         CREATE INDEX IF NOT EXISTS postings_doc_id ON postings (doc_id);
+         CREATE TABLE IF NOT EXISTS similarity_edges (
+             document_id INTEGER NOT NULL,
+             doc_id INTEGER NOT NULL,
+             similarity REAL NOT NULL,
+             containment REAL NOT NULL,
+             reverse_containment REAL NOT NULL,
+             PRIMARY KEY (document_id, doc_id)
+         );
+         CREATE INDEX IF NOT EXISTS similarity_edges_doc_id ON similarity_edges (doc_id);
+         CREATE TABLE IF NOT EXISTS signatures ( doc_id INTEGER PRIMARY KEY, signature BLOB NOT NULL, shingle_count INTEGER NOT NULL );
+         CREATE TABLE IF NOT EXISTS lsh_buckets ( band INTEGER NOT NULL, bucket INTEGER NOT NULL, doc_id INTEGER NOT NULL );
+         CREATE INDEX IF NOT EXISTS lsh_buckets_bucket ON lsh_buckets (band, bucket);
+         CREATE INDEX IF NOT EXISTS lsh_buckets_doc_id ON lsh_buckets (doc_id);
+         CREATE TABLE IF NOT EXISTS content_hashes ( hash INTEGER NOT NULL, doc_id INTEGER PRIMARY KEY );
+         CREATE INDEX IF NOT EXISTS content_hashes_hash ON content_hashes (hash);",
+    )?;
+    fields::create_tables(conn)?;
+    metadata::create_tables(conn)?;
+    LabelSet::create_table(conn)
+}
+
+fn encode_signature(signature: &[u64]) -> Vec<u8> {
+    signature
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn decode_signature(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+/// Outcome of `Ingestor::ingest`.
+#[derive(Debug, Default)]
+pub struct Ingested {
+    pub inserted: Vec<u32>,
+    /// ids that were already taken
+    pub skipped: Vec<u32>,
+    pub touched: Touched,
+}
+
+/// Outcome of `Ingestor::update` and `Ingestor::delete`.
+#[derive(Debug, Default)]
+pub struct Edited {
+    pub edited: Vec<u32>,
+    /// ids that are not in the database
+    pub missing: Vec<u32>,
+    pub touched: Touched,
+}
+
+/// Inverse index tokens and documents whose derived rows need to be rebuilt.
+#[derive(Debug, Default)]
+pub struct Touched {
+    pub tokens: BTreeSet<String>,
+    pub documents: BTreeSet<u32>,
+}
+
+impl Touched {
+    pub fn extend(&mut self, other: Touched) {
+        self.tokens.extend(other.tokens);
+        self.documents.extend(other.documents);
+    }
+}
+
+/// Adds records to a database chunk by chunk, without holding the rest of the corpus in memory.
+///
+/// Each chunk is written to `records` and `postings`, its MinHash signatures are stored and
+/// compared with every candidate the LSH buckets turn up (earlier chunks included), and the
+/// resulting pairs go to `similarity_edges`. `materialize` then rebuilds the `inverse_index`
+/// and `similarities` rows the api reads.
+pub struct Ingestor<'a> {
+    conn: &'a Connection,
+    pub settings: IngestSettings,
+    hash_funcs: Vec<HashFunc>,
+    lsh: LshIndex,
+}
+
+impl<'a> Ingestor<'a> {
+    pub fn new(conn: &'a Connection, settings: IngestSettings) -> rusqlite::Result<Ingestor<'a>> {
+        // every record of a new database goes through `ingest`
+        let fresh = !fields::table_exists(conn, "records")?;
+        create_tables(conn)?;
+        settings.save(conn)?;
+        if fresh {
+            mark_indexed(conn)?;
+        }
+
+        let ingestor = Ingestor {
+            conn,
+            hash_funcs: generate_hash_funcs(
+                settings.minhash_length,
+                &mut StdRng::seed_from_u64(settings.seed),
+            ),
+            lsh: LshIndex::for_signature_length(settings.minhash_length, settings.bands),
+            settings,
+        };
+        ingestor.upgrade_hashes()?;
+        Ok(ingestor)
+    }
+
+    /// Recomputes `content_hashes` and `lsh_buckets` if they were stored with another hash than
+    /// `HASH_VERSION`, such as the `DefaultHasher` of earlier builds. Lookups would find no
+    /// duplicates and no candidates in them otherwise.
+    fn upgrade_hashes(&self) -> rusqlite::Result<()> {
+        let version: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM metadata WHERE key = 'hash_version'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if version.as_deref() == Some(HASH_VERSION) {
+            return Ok(());
+        }
+
+        let tx = JoinedTransaction::begin(self.conn)?;
+        self.conn.execute("DELETE FROM content_hashes", ())?;
+        self.conn.execute("DELETE FROM lsh_buckets", ())?;
+        {
+            let mut stmt_records = self.conn.prepare(
+                "SELECT records.* FROM records JOIN signatures ON signatures.doc_id = records.id",
+            )?;
+            let mut stmt_hash = self
+                .conn
+                .prepare("INSERT INTO content_hashes (hash, doc_id) VALUES (?1, ?2)")?;
+            let records = stmt_records.query_map([], |row| Record::try_from(row))?;
+            for record in records {
+                let record = self.settings.normalization.record(&record?);
+                let hash = dedup::content_hash(&dedup::normalized_content(&record)) as i64;
+                stmt_hash.execute(params![hash, record.id])?;
+            }
+
+            let mut stmt_signatures = self
+                .conn
+                .prepare("SELECT doc_id, signature FROM signatures")?;
+            let mut stmt_bucket = self
+                .conn
+                .prepare("INSERT INTO lsh_buckets VALUES (?1, ?2, ?3)")?;
+            let signatures = stmt_signatures.query_map([], |row| {
+                Ok((row.get::<_, u32>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?;
+            for signature in signatures {
+                let (doc_id, signature) = signature?;
+                let signature = decode_signature(&signature);
+                for (band, bucket) in self.lsh.band_keys(&signature).enumerate() {
+                    stmt_bucket.execute(params![band as i64, bucket as i64, doc_id])?;
+                }
+            }
+        }
+        self.conn.execute(
+            "INSERT OR REPLACE INTO metadata VALUES ('hash_version', ?1)",
+            params![HASH_VERSION],
+        )?;
+        tx.commit()
+    }
+
+    /// Ingests one chunk of records. Records whose id is already taken are skipped.
+    pub fn ingest(&self, records: &[Record]) -> rusqlite::Result<Ingested> {
+        let tx = JoinedTransaction::begin(self.conn)?;
+
+        // records ---------------------------------------------------------------------------------
+        let mut inserted: Vec<&Record> = Vec::new();
+        let mut skipped: Vec<u32> = Vec::new();
+        let mut stmt_record = self
+            .conn
+            .prepare("INSERT INTO records (id, title, text, label) VALUES (?1, ?2, ?3, ?4)")?;
+        for record in records {
+            match stmt_record.execute(params![
+                record.id,
+                record.title,
+                record.text,
+                record.label.to_string()
+            ]) {
+                Ok(_) => inserted.push(record),
+                Err(rusqlite::Error::SqliteFailure(err, _))
+                    if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+                {
+                    skipped.push(record.id);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        metadata::save(self.conn, inserted.iter().copied())?;
+        let touched = self.index(&inserted)?;
+
+        tx.commit()?;
+        Ok(Ingested {
+            inserted: inserted.iter().map(|record| record.id).collect(),
+            skipped,
+            touched,
+        })
+    }
+
+    /// Indexes records that are already in the `records` table: postings, content hashes,
+    /// signatures, LSH buckets and similarity edges against everything indexed before them.
+    fn index(&self, inserted: &[&Record]) -> rusqlite::Result<Touched> {
+        let mut touched = Touched::default();
+
+        // everything below indexes the normalized text, `records` keeps the original
+        let normalization = self.settings.normalization;
+        let normalized: Vec<Record> = inserted
+            .iter()
+            .map(|record| normalization.record(record))
+            .collect();
+
+        // postings --------------------------------------------------------------------------------
+        {
+            let mut stmt_posting = self
+                .conn
+                .prepare("INSERT OR IGNORE INTO postings (token, doc_id) VALUES (?1, ?2)")?;
+            for record in &normalized {
+                let combined_string = [record.text.as_str(), record.title.as_str()].join(" ");
+                for token in tokenize(&combined_string) {
+                    let token = token.replace('"', "”");
+                    stmt_posting.execute(params![token, record.id])?;
+                    touched.tokens.insert(token);
+                }
+            }
+            fields::insert_postings(self.conn, &normalized)?;
+        }
+
+        // exact duplicates reuse the signature of the record they duplicate --------------------
+        let mut representatives: HashMap<u32, u32> = HashMap::new();
+        {
+            let mut stmt_lookup = self
+                .conn
+                .prepare("SELECT doc_id FROM content_hashes WHERE hash = (?1) ORDER BY doc_id")?;
+            let mut stmt_record = self.conn.prepare("SELECT * FROM records WHERE id = (?1)")?;
+            let mut stmt_insert = self
+                .conn
+                .prepare("INSERT OR REPLACE INTO content_hashes (hash, doc_id) VALUES (?1, ?2)")?;
+
+            for record in &normalized {
+                let content = dedup::normalized_content(record);
+                let hash = dedup::content_hash(&content) as i64;
+
+                let existing: Vec<u32> = stmt_lookup
+                    .query_map(params![hash], |row| row.get(0))?
+                    .collect::<rusqlite::Result<_>>()?;
+                for id in existing {
+                    let stored = stmt_record
+                        .query_row(params![id], |row| Record::try_from(row))
+                        .optional()?;
+                    if stored.is_some_and(|stored| {
+                        dedup::normalized_content(&normalization.record(&stored)) == content
+                    }) {
+                        // point at the start of the chain if `id` is a duplicate from this chunk
+                        let representative = representatives.get(&id).copied().unwrap_or(id);
+                        representatives.insert(record.id, representative);
+                        break;
+                    }
+                }
+
+                stmt_insert.execute(params![hash, record.id])?;
+            }
+        }
+
+        // signatures ------------------------------------------------------------------------------
+        let documents: HashMap<u32, String> = normalized
+            .iter()
+            .filter(|record| !representatives.contains_key(&record.id))
+            .map(|record| (record.id, record.to_db_string()))
+            .collect();
+        let shingled = create_shingles(&documents, self.settings.shingle_size);
+        let hash_funcs = &self.hash_funcs;
+        let mut signatures: HashMap<u32, (Vec<u64>, usize)> = shingled
+            .par_iter()
+            .map(|(id, shingles)| {
+                (
+                    *id,
+                    (
+                        generate_minhash_signature(shingles, hash_funcs),
+                        shingles.len(),
+                    ),
+                )
+            })
+            .collect();
+        for (id, representative) in &representatives {
+            let signature = match signatures.get(representative) {
+                Some(signature) => signature.clone(),
+                None => self.signature(*representative)?,
+            };
+            signatures.insert(*id, signature);
+        }
+
+        {
+            let mut stmt_signature = self
+                .conn
+                .prepare("INSERT OR REPLACE INTO signatures VALUES (?1, ?2, ?3)")?;
+            let mut stmt_bucket = self
+                .conn
+                .prepare("INSERT INTO lsh_buckets VALUES (?1, ?2, ?3)")?;
+            for record in inserted {
+                let (signature, shingle_count) = &signatures[&record.id];
+                stmt_signature.execute(params![
+                    record.id,
+                    encode_signature(signature),
+                    *shingle_count as i64
+                ])?;
+                for (band, bucket) in self.lsh.band_keys(signature).enumerate() {
+                    stmt_bucket.execute(params![band as i64, bucket as i64, record.id])?;
+                }
+            }
+        }
+
+        // similarity edges ------------------------------------------------------------------------
+        let mut shingles = shingled;
+        for record in inserted {
+            let (signature, _) = &signatures[&record.id];
+            touched.documents.insert(record.id);
+
+            for candidate in self.candidates(signature)? {
+                let candidate_signature = if candidate == record.id {
+                    signature.clone()
+                } else {
+                    self.signature(candidate)?.0
+                };
+
+                let e = minhash_similarity(signature, &candidate_signature);
+                if self.settings.metric == SimilarityMetric::Jaccard && e < self.settings.threshold
+                {
+                    continue;
+                }
+                for id in [record.id, candidate] {
+                    if let Entry::Vacant(entry) = shingles.entry(id) {
+                        entry.insert(self.shingles(id)?);
+                    }
+                }
+                let pair =
+                    PairSimilarity::from_shingles(e, &shingles[&record.id], &shingles[&candidate]);
+
+                if pair.score(self.settings.metric) >= self.settings.threshold {
+                    self.insert_edges(record.id, candidate, &pair)?;
+                    touched.documents.insert(candidate);
+                }
+            }
+        }
+
+        Ok(touched)
+    }
+
+    /// Replaces stored records with `records` (matched by id) and re-indexes them. Ids that are
+    /// not in the database are reported as missing.
+    pub fn update(&self, records: &[Record]) -> rusqlite::Result<Edited> {
+        let tx = JoinedTransaction::begin(self.conn)?;
+        let mut edited = Edited::default();
+
+        let mut updated: Vec<&Record> = Vec::new();
+        {
+            let mut stmt_update = self.conn.prepare(
+                "UPDATE records SET title = (?2), text = (?3), label = (?4) WHERE id = (?1)",
+            )?;
+            let mut stmt_metadata = self
+                .conn
+                .prepare("DELETE FROM record_metadata WHERE record_id = (?1)")?;
+            for record in records {
+                let changed = stmt_update.execute(params![
+                    record.id,
+                    record.title,
+                    record.text,
+                    record.label.to_string()
+                ])?;
+                if changed == 0 {
+                    edited.missing.push(record.id);
+                    continue;
+                }
+                stmt_metadata.execute(params![record.id])?;
+                updated.push(record);
+            }
+        }
+
+        let ids: Vec<u32> = updated.iter().map(|record| record.id).collect();
+        edited.touched = self.unindex(&ids)?;
+        metadata::save(self.conn, updated.iter().copied())?;
+        edited.touched.extend(self.index(&updated)?);
+        edited.edited = ids;
+
+        tx.commit()?;
+        Ok(edited)
+    }
+
+    /// Deletes records along with everything derived from them. Ids that are not in the database
+    /// are reported as missing.
+    pub fn delete(&self, ids: &[u32]) -> rusqlite::Result<Edited> {
+        let tx = JoinedTransaction::begin(self.conn)?;
+        let mut edited = Edited::default();
+
+        {
+            let mut stmt_record = self.conn.prepare("DELETE FROM records WHERE id = (?1)")?;
+            let mut stmt_metadata = self
+                .conn
+                .prepare("DELETE FROM record_metadata WHERE record_id = (?1)")?;
+            for id in ids {
+                if stmt_record.execute(params![id])? == 0 {
+                    edited.missing.push(*id);
+                    continue;
+                }
+                stmt_metadata.execute(params![id])?;
+                edited.edited.push(*id);
+            }
+        }
+        edited.touched = self.unindex(&edited.edited)?;
+
+        tx.commit()?;
+        Ok(edited)
+    }
+
+    /// Drops the postings, signatures, LSH buckets, content hashes and similarity edges of `ids`.
+    /// The returned tokens and documents include every neighbour that lost an edge.
+    fn unindex(&self, ids: &[u32]) -> rusqlite::Result<Touched> {
+        let mut touched = Touched::default();
+        let mut stmt_tokens = self
+            .conn
+            .prepare("SELECT token FROM postings WHERE doc_id = (?1)")?;
+        let mut stmt_neighbours = self
+            .conn
+            .prepare("SELECT doc_id FROM similarity_edges WHERE document_id = (?1)")?;
+
+        for id in ids {
+            for token in stmt_tokens.query_map(params![id], |row| row.get(0))? {
+                touched.tokens.insert(token?);
+            }
+            for neighbour in stmt_neighbours.query_map(params![id], |row| row.get(0))? {
+                touched.documents.insert(neighbour?);
+            }
+            touched.documents.insert(*id);
+
+            self.conn
+                .execute("DELETE FROM postings WHERE doc_id = (?1)", params![id])?;
+            self.conn.execute(
+                "DELETE FROM field_postings WHERE doc_id = (?1)",
+                params![id],
+            )?;
+            self.conn.execute(
+                "DELETE FROM similarity_edges WHERE document_id = (?1) OR doc_id = (?1)",
+                params![id],
+            )?;
+            self.conn
+                .execute("DELETE FROM signatures WHERE doc_id = (?1)", params![id])?;
+            self.conn
+                .execute("DELETE FROM lsh_buckets WHERE doc_id = (?1)", params![id])?;
+            self.conn.execute(
+                "DELETE FROM content_hashes WHERE doc_id = (?1)",
+                params![id],
+            )?;
+        }
+        Ok(touched)
+    }
+
+    /// Indexes the records of a database the ingestor didn't build, so records can be added,
+    /// edited and deleted one at a time, and marks it as indexed once the last chunk is in.
+    ///
+    /// Only the ingestor's own tables are written, `inverse_index` and `similarities` keep the
+    /// rows the full build computed until they are touched. Chunks are committed in id order, so
+    /// an interrupted run picks up after the last one. Databases the ingestor maintained before
+    /// fields were indexed separately only get their `field_postings`. The numeric labels of the
+    /// records are stored in `labels` if the database has none yet.
+    pub fn index_existing(&self, chunk_size: usize) -> rusqlite::Result<()> {
+        let last_indexed = self.last_id("signatures")?;
+        let last_fields_indexed = self.last_id("field_postings")?;
+        if last_indexed.is_none() {
+            let tx = JoinedTransaction::begin(self.conn)?;
+            self.seed_edges()?;
+            tx.commit()?;
+        }
+
+        let normalization = self.settings.normalization;
+        if last_fields_indexed < last_indexed {
+            self.for_each_chunk(chunk_size, last_fields_indexed, last_indexed, |chunk| {
+                let normalized: Vec<Record> = chunk
+                    .iter()
+                    .map(|record| normalization.record(record))
+                    .collect();
+                fields::insert_postings(self.conn, &normalized)
+            })?;
+        }
+        self.for_each_chunk(chunk_size, last_indexed, None, |chunk| {
+            self.index(&chunk.iter().collect::<Vec<&Record>>())?;
+            Ok(())
+        })?;
+
+        let tx = JoinedTransaction::begin(self.conn)?;
+        fields::materialize(self.conn, None)?;
+        LabelSet::save_seeded(self.conn)?;
+        mark_indexed(self.conn)?;
+        tx.commit()
+    }
+
+    /// Largest document id in `table`, which has a `doc_id` column.
+    fn last_id(&self, table: &str) -> rusqlite::Result<Option<u32>> {
+        self.conn
+            .query_row(&format!("SELECT MAX(doc_id) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+    }
+
+    /// Copies the pairs of the `similarities` rows into `similarity_edges`. A database built in
+    /// memory compared every pair, and the ones LSH doesn't turn up as candidates would be lost
+    /// the first time a document's row is rebuilt from the edges.
+    fn seed_edges(&self) -> rusqlite::Result<()> {
+        let mut stmt_rows = self
+            .conn
+            .prepare("SELECT document_id, similar_documents FROM similarities")?;
+        let mut stmt_edge = self
+            .conn
+            .prepare("INSERT OR IGNORE INTO similarity_edges VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        let rows = stmt_rows.query_map([], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (document_id, similar_documents) = row?;
+            for similar in parse_similar_documents(&similar_documents) {
+                // databases from before containment was stored only have the similarity
+                stmt_edge.execute(params![
+                    document_id,
+                    similar.doc_id,
+                    similar.similarity,
+                    similar.containment.unwrap_or(similar.similarity),
+                    similar.reverse_containment.unwrap_or(similar.similarity)
+                ])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `f` on the stored records after `after` and up to `up_to`, `chunk_size` at a time in
+    /// id order, one transaction each (unless the caller opened one).
+    fn for_each_chunk(
+        &self,
+        chunk_size: usize,
+        after: Option<u32>,
+        up_to: Option<u32>,
+        mut f: impl FnMut(&[Record]) -> rusqlite::Result<()>,
+    ) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM records WHERE id > (?1) AND id <= (?2) ORDER BY id LIMIT (?3)",
+        )?;
+        let mut last_id: i64 = after.map_or(-1, i64::from);
+        let up_to: i64 = up_to.map_or(i64::MAX, i64::from);
+
+        loop {
+            let chunk: Vec<Record> = stmt
+                .query_map(params![last_id, up_to, chunk_size as i64], |row| {
+                    Record::try_from(row)
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            let Some(last) = chunk.last() else {
+                break;
+            };
+            last_id = last.id.into();
+
+            let tx = JoinedTransaction::begin(self.conn)?;
+            f(&chunk)?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    fn signature(&self, doc_id: u32) -> rusqlite::Result<(Vec<u64>, usize)> {
+        self.conn
+            .prepare_cached("SELECT signature, shingle_count FROM signatures WHERE doc_id = (?1)")?
+            .query_row(params![doc_id], |row| {
+                Ok((
+                    decode_signature(&row.get::<_, Vec<u8>>(0)?),
+                    row.get::<_, i64>(1)? as usize,
+                ))
+            })
+    }
+
+    /// Shingles of a stored record, normalized the way it was indexed.
+    fn shingles(&self, doc_id: u32) -> rusqlite::Result<HashSet<String>> {
+        let record = self
+            .conn
+            .prepare_cached("SELECT * FROM records WHERE id = (?1)")?
+            .query_row(params![doc_id], |row| Record::try_from(row))?;
+        let document = self.settings.normalization.record(&record).to_db_string();
+        Ok(create_shingles(
+            &HashMap::from([(doc_id, document)]),
+            self.settings.shingle_size,
+        )
+        .remove(&doc_id)
+        .unwrap_or_default())
+    }
+
+    /// Every stored document sharing at least one LSH band with `signature`.
+    fn candidates(&self, signature: &[u64]) -> rusqlite::Result<BTreeSet<u32>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT doc_id FROM lsh_buckets WHERE band = (?1) AND bucket = (?2)")?;
+        let mut candidates = BTreeSet::new();
+        for (band, bucket) in self.lsh.band_keys(signature).enumerate() {
+            for id in stmt.query_map(params![band as i64, bucket as i64], |row| row.get(0))? {
+                candidates.insert(id?);
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Stores the pair in both directions.
+    fn insert_edges(&self, a: u32, b: u32, pair: &PairSimilarity) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT OR REPLACE INTO similarity_edges VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        stmt.execute(params![
+            a,
+            b,
+            pair.similarity,
+            pair.containment,
+            pair.reverse_containment
+        ])?;
+        stmt.execute(params![
+            b,
+            a,
+            pair.similarity,
+            pair.reverse_containment,
+            pair.containment
+        ])?;
+        Ok(())
+    }
+
+    /// Rebuilds the `inverse_index`, `field_index` and `similarities` rows from `postings`,
+    /// `field_postings` and `similarity_edges`, either completely or only for what `touched`
+    /// lists.
+    pub fn materialize(&self, touched: Option<&Touched>) -> rusqlite::Result<()> {
+        materialize(self.conn, touched)
+    }
+}
+
+const INVERSE_INDEX_ROWS: &str = "
+    SELECT token, '[' || group_concat(doc_id, ', ' ORDER BY doc_id) || ']'
+    FROM postings";
+
+// same layout `create_sqlite_file` writes, trailing comma included
+const SIMILARITIES_ROWS: &str = "
+    SELECT document_id, '[' || group_concat(json_object(
+        'containment', containment,
+        'doc_id', doc_id,
+        'reverse_containment', reverse_containment,
+        'similarity', similarity
+    ), ',' ORDER BY doc_id) || ',]'
+    FROM similarity_edges";
+
+pub fn materialize(conn: &Connection, touched: Option<&Touched>) -> rusqlite::Result<()> {
+    let tx = JoinedTransaction::begin(conn)?;
+
+    match touched {
+        None => {
+            conn.execute("DELETE FROM inverse_index", ())?;
+            conn.execute(
+                &format!(
+                    "INSERT INTO inverse_index {} GROUP BY token ORDER BY token",
+                    INVERSE_INDEX_ROWS
+                ),
+                (),
+            )?;
+            conn.execute("DELETE FROM similarities", ())?;
+            conn.execute(
+                &format!(
+                    "INSERT INTO similarities {} GROUP BY document_id ORDER BY document_id",
+                    SIMILARITIES_ROWS
+                ),
+                (),
+            )?;
+            fields::materialize(conn, None)?;
+        }
+        Some(touched) => {
+            let mut stmt_delete = conn.prepare("DELETE FROM inverse_index WHERE string = (?1)")?;
+            let mut stmt_insert = conn.prepare(&format!(
+                "INSERT INTO inverse_index {} WHERE token = (?1) GROUP BY token",
+                INVERSE_INDEX_ROWS
+            ))?;
+            for token in &touched.tokens {
+                stmt_delete.execute(params![token])?;
+                stmt_insert.execute(params![token])?;
+            }
+            fields::materialize(conn, Some(&touched.tokens))?;
+
+            let mut stmt_delete =
+                conn.prepare("DELETE FROM similarities WHERE document_id = (?1)")?;
+            let mut stmt_insert = conn.prepare(&format!(
+                "INSERT INTO similarities {} WHERE document_id = (?1) GROUP BY document_id",
+                SIMILARITIES_ROWS
+            ))?;
+            for document in &touched.documents {
+                stmt_delete.execute(params![document])?;
+                stmt_insert.execute(params![document])?;
+            }
+        }
+    }
+
+    tx.commit()
+}
+
+/// `process --streaming`: reads the input `chunk_size` records at a time and ingests each chunk
+/// before reading the next, so memory use does not grow with the size of the input.
+pub fn run_streaming(
+    args: &Args,
+    settings: IngestSettings,
+    file_path: &Path,
+    db_file: &str,
+    similarities_file_path: &str,
+) {
+    let chunk_size = args.parse_or("chunk-size", 10_000usize).max(1);
+    let load_options = LoadOptions::from_args(args);
+    if load_options.on_duplicate_id == OnDuplicateId::Last {
+        cli::exit_with_usage("--streaming can't keep the last of several rows with the same id");
+    }
+
+    let conn = Connection::open(db_file).expect("Failed to open database");
+    let ingestor = Ingestor::new(&conn, settings).expect("Failed to prepare database");
+    eprintln!(
+        "warning: --streaming only compares the pairs that share one of {} LSH bands, so it can \
+         miss pairs that a run without --streaming finds",
+        ingestor.lsh.bands()
+    );
+
+    let mut loader = RecordLoader::open(file_path, &load_options).tracking_ids_on_disk();
+    let mut total = 0;
+
+    // chunks before a row that stops the run are already committed
+    let mut failure = None;
+    loop {
+        let chunk = match loader.take_chunk(chunk_size) {
+            Ok(chunk) if chunk.is_empty() => break,
+            Ok(chunk) => chunk,
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        };
+
+        let ingested = ingestor.ingest(&chunk).expect("Failed to ingest chunk");
+        for id in &ingested.skipped {
+            println!("Skipping record {}: id already exists", id);
+        }
+        total += chunk.len();
+        println!("ingested {} records", total);
+    }
+    loader.summary.print(&load_options);
+    if let Some(e) = failure {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    loader.labels.save(&conn).expect("Failed to store labels");
+
+    println!("Building the inverse index and similarities tables...");
+    ingestor.materialize(None).expect("Failed to build tables");
+
+    println!("Generating the csv file");
+    let mut writer = SimilaritiesCsv::create(similarities_file_path);
+    let mut stmt = conn
+        .prepare(
+            "SELECT document_id, doc_id, similarity, containment, reverse_containment
+             FROM similarity_edges ORDER BY document_id, doc_id",
+        )
+        .unwrap();
+    let edges = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, u32>(1)?,
+                PairSimilarity {
+                    similarity: row.get(2)?,
+                    containment: row.get(3)?,
+                    reverse_containment: row.get(4)?,
+                },
+            ))
+        })
+        .unwrap();
+    // edges come grouped by document, only one document's are held at a time
+    let mut row: Option<(u32, Vec<(u32, PairSimilarity)>)> = None;
+    for edge in edges {
+        let (document_id, doc_id, pair) = edge.unwrap();
+        match &mut row {
+            Some((current, similar)) if *current == document_id => similar.push((doc_id, pair)),
+            _ => {
+                if let Some((current, similar)) = row.replace((document_id, vec![(doc_id, pair)])) {
+                    writer.write(current, similar);
+                }
+            }
+        }
+    }
+    if let Some((current, similar)) = row {
+        writer.write(current, similar);
+    }
+    writer.finish();
+}
@@ -0,0 +1,435 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, Lines},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    cli::{exit_with_usage, Args},
+    labels::LabelSet,
+    metadata::{self, MetadataSpec},
+};
+
+/// Layout of an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Delimited text with one record per row.
+    Csv,
+    /// One json object per line.
+    Jsonl,
+    /// A directory with one text file per record.
+    TextDir,
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(InputFormat::Jsonl),
+            "text" | "text-dir" => Ok(InputFormat::TextDir),
+            _ => Err(format!("unknown input format `{}`", s)),
+        }
+    }
+}
+
+impl InputFormat {
+    /// Directories are read as text files, `.jsonl` / `.ndjson` files as json lines and
+    /// anything else as csv.
+    pub fn infer(path: &Path) -> InputFormat {
+        if path.is_dir() {
+            return InputFormat::TextDir;
+        }
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("jsonl") | Some("ndjson") => InputFormat::Jsonl,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+/// Source field each `Record` field is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub id: String,
+    pub title: String,
+    pub text: String,
+    pub label: String,
+}
+
+impl ColumnMapping {
+    /// Mapping for files whose fields are already named after `Record`.
+    pub fn named() -> ColumnMapping {
+        ColumnMapping {
+            id: "id".to_string(),
+            title: "title".to_string(),
+            text: "text".to_string(),
+            label: "label".to_string(),
+        }
+    }
+
+    /// Mapping for csv files without a header row, columns in the `id;title;text;label` order.
+    pub fn positional() -> ColumnMapping {
+        ColumnMapping {
+            id: "0".to_string(),
+            title: "1".to_string(),
+            text: "2".to_string(),
+            label: "3".to_string(),
+        }
+    }
+
+    /// Applies a `field=column,...` spec on top of `self`, e.g. `id=doc_id,text=body`.
+    pub fn with_overrides(mut self, spec: &str) -> Result<ColumnMapping, String> {
+        for pair in spec.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (field, column) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `field=column`, found `{}`", pair))?;
+            let column = column.trim().to_string();
+            match field.trim() {
+                "id" => self.id = column,
+                "title" => self.title = column,
+                "text" => self.text = column,
+                "label" => self.label = column,
+                other => return Err(format!("unknown record field `{}`", other)),
+            }
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InputOptions {
+    /// `None` picks the format from the path.
+    pub format: Option<InputFormat>,
+    pub delimiter: u8,
+    pub has_headers: bool,
+    pub columns: ColumnMapping,
+    /// Number rows from 1 instead of reading the id column.
+    pub generate_ids: bool,
+    /// Label, by name or id, for rows without a label field.
+    pub default_label: Option<String>,
+    /// Accepted labels, `None` accepts any.
+    pub labels: Option<LabelSet>,
+    pub metadata: MetadataSpec,
+}
+
+impl InputOptions {
+    pub fn from_args(args: &Args) -> InputOptions {
+        let format = args.get("format").map(|format| {
+            format
+                .parse::<InputFormat>()
+                .unwrap_or_else(|e| exit_with_usage(&e))
+        });
+        let delimiter = match args.get_or("delimiter", ";").as_str() {
+            "tab" | "\\t" => b'\t',
+            delimiter if delimiter.len() == 1 => delimiter.as_bytes()[0],
+            delimiter => exit_with_usage(&format!(
+                "--delimiter must be a single character, found `{}`",
+                delimiter
+            )),
+        };
+        let has_headers = !args.has("no-headers");
+
+        let defaults = if has_headers {
+            ColumnMapping::named()
+        } else {
+            ColumnMapping::positional()
+        };
+        let columns = match args.get("columns") {
+            Some(spec) => defaults
+                .with_overrides(spec)
+                .unwrap_or_else(|e| exit_with_usage(&e)),
+            None => defaults,
+        };
+
+        InputOptions {
+            format,
+            delimiter,
+            has_headers,
+            columns,
+            generate_ids: args.has("generate-ids"),
+            default_label: args.get("default-label").map(str::to_string),
+            labels: args
+                .get("labels")
+                .map(|spec| LabelSet::parse_spec(spec).unwrap_or_else(|e| exit_with_usage(&e))),
+            metadata: args
+                .get("metadata")
+                .map(|spec| metadata::parse_spec(spec).unwrap_or_else(|e| exit_with_usage(&e))),
+        }
+    }
+
+    pub fn format_for(&self, path: &Path) -> InputFormat {
+        self.format.unwrap_or_else(|| InputFormat::infer(path))
+    }
+}
+
+impl Default for InputOptions {
+    fn default() -> Self {
+        InputOptions {
+            format: None,
+            delimiter: b';',
+            has_headers: true,
+            columns: ColumnMapping::named(),
+            generate_ids: false,
+            default_label: None,
+            labels: None,
+            metadata: None,
+        }
+    }
+}
+
+/// One row of an input, before it is mapped onto a `Record`.
+pub struct RawRow {
+    /// Line number, or file number for text directories.
+    pub line: u64,
+    /// The row as it was read, for the rejects file.
+    pub content: String,
+    /// Field values by column name, or why the row could not be read.
+    pub fields: Result<HashMap<String, String>, String>,
+}
+
+pub type RowReader = Box<dyn Iterator<Item = RawRow>>;
+
+/// Opens `path` with the reader for its format.
+pub fn open_rows(path: &Path, options: &InputOptions) -> RowReader {
+    match options.format_for(path) {
+        InputFormat::Csv => Box::new(CsvRows::open(path, options)),
+        InputFormat::Jsonl => Box::new(JsonlRows::open(path)),
+        InputFormat::TextDir => Box::new(TextDirRows::open(path)),
+    }
+}
+
+struct CsvRows {
+    reader: csv::Reader<File>,
+    /// Column names, `None` when the file has no header row and columns go by position.
+    headers: Option<csv::StringRecord>,
+    delimiter: char,
+}
+
+impl CsvRows {
+    fn open(path: &Path, options: &InputOptions) -> CsvRows {
+        let file = File::open(path).expect("Failed to open file");
+
+        // rows with a wrong number of fields are reported by the loader instead of the reader
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .has_headers(options.has_headers)
+            .flexible(true)
+            .from_reader(file);
+        let headers = options.has_headers.then(|| {
+            reader
+                .headers()
+                .expect("Failed to read csv headers")
+                .clone()
+        });
+
+        CsvRows {
+            reader,
+            headers,
+            delimiter: char::from(options.delimiter),
+        }
+    }
+}
+
+impl Iterator for CsvRows {
+    type Item = RawRow;
+
+    fn next(&mut self) -> Option<RawRow> {
+        let mut raw = csv::ByteRecord::new();
+        let line = self.reader.position().line();
+
+        match self.reader.read_byte_record(&mut raw) {
+            Ok(false) => return None,
+            Ok(true) => {}
+            Err(e) => {
+                return Some(RawRow {
+                    line,
+                    content: String::new(),
+                    fields: Err(e.to_string()),
+                })
+            }
+        }
+
+        let line = raw.position().map_or(line, |position| position.line());
+        let content = raw
+            .iter()
+            .map(|field| String::from_utf8_lossy(field).into_owned())
+            .collect::<Vec<String>>()
+            .join(&self.delimiter.to_string());
+
+        let fields = csv::StringRecord::from_byte_record(raw)
+            .map_err(|e| e.to_string())
+            .and_then(|row| match &self.headers {
+                Some(headers) if row.len() != headers.len() => Err(format!(
+                    "expected {} fields, found {}",
+                    headers.len(),
+                    row.len()
+                )),
+                Some(headers) => Ok(headers
+                    .iter()
+                    .zip(row.iter())
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect()),
+                None => Ok(row
+                    .iter()
+                    .enumerate()
+                    .map(|(position, value)| (position.to_string(), value.to_string()))
+                    .collect()),
+            });
+
+        Some(RawRow {
+            line,
+            content,
+            fields,
+        })
+    }
+}
+
+struct JsonlRows {
+    lines: Lines<BufReader<File>>,
+    line: u64,
+}
+
+impl JsonlRows {
+    fn open(path: &Path) -> JsonlRows {
+        let file = File::open(path).expect("Failed to open file");
+        JsonlRows {
+            lines: BufReader::new(file).lines(),
+            line: 0,
+        }
+    }
+}
+
+/// Flattens a json object into strings, so numbers and strings map onto the same fields.
+fn json_fields(content: &str) -> Result<HashMap<String, String>, String> {
+    let value: serde_json::Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| "expected a json object".to_string())?;
+
+    Ok(object
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| {
+            let value = match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            (name.clone(), value)
+        })
+        .collect())
+}
+
+impl Iterator for JsonlRows {
+    type Item = RawRow;
+
+    fn next(&mut self) -> Option<RawRow> {
+        loop {
+            let content = self.lines.next()?;
+            self.line += 1;
+
+            let content = match content {
+                Ok(content) => content,
+                Err(e) => {
+                    return Some(RawRow {
+                        line: self.line,
+                        content: String::new(),
+                        fields: Err(e.to_string()),
+                    })
+                }
+            };
+            if content.trim().is_empty() {
+                continue;
+            }
+
+            return Some(RawRow {
+                line: self.line,
+                fields: json_fields(&content),
+                content,
+            });
+        }
+    }
+}
+
+/// Reads every file in a directory as one record, titled by the file name.
+///
+/// Files in a subdirectory get the subdirectory's name as their `label` field, so a
+/// `<dir>/<label>/<file>` layout can be loaded as is. Files are read in path order.
+struct TextDirRows {
+    files: std::vec::IntoIter<(PathBuf, Option<String>)>,
+    line: u64,
+}
+
+impl TextDirRows {
+    fn open(path: &Path) -> TextDirRows {
+        let mut files = Vec::new();
+
+        for entry in sorted_entries(path) {
+            if entry.is_dir() {
+                let label = entry
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+                files.extend(
+                    sorted_entries(&entry)
+                        .into_iter()
+                        .filter(|file| file.is_file())
+                        .map(|file| (file, label.clone())),
+                );
+            } else if entry.is_file() {
+                files.push((entry, None));
+            }
+        }
+
+        TextDirRows {
+            files: files.into_iter(),
+            line: 0,
+        }
+    }
+}
+
+/// Entries of `dir` sorted by path, skipping hidden ones.
+fn sorted_entries(dir: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("Failed to read input directory")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            !path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+impl Iterator for TextDirRows {
+    type Item = RawRow;
+
+    fn next(&mut self) -> Option<RawRow> {
+        let (path, label) = self.files.next()?;
+        self.line += 1;
+
+        let fields = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .map(|text| {
+                let title = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                let mut fields =
+                    HashMap::from([("title".to_string(), title), ("text".to_string(), text)]);
+                if let Some(label) = &label {
+                    fields.insert("label".to_string(), label.clone());
+                }
+                fields
+            });
+
+        Some(RawRow {
+            line: self.line,
+            content: path.display().to_string(),
+            fields,
+        })
+    }
+}
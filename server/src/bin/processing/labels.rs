@@ -0,0 +1,259 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{BTreeMap, HashMap};
+
+/// The classes records can be labelled with, by id and by name.
+///
+/// A set built from `--labels` is closed: only its labels are accepted. Otherwise labels are
+/// added as they are first seen, numeric values keep their number as id and names get the next
+/// free id. An open set takes either numbers or names, so the id a name was given is never
+/// mistaken for a numeric label that means something else.
+#[derive(Debug, Clone, Default)]
+pub struct LabelSet {
+    names: BTreeMap<u32, String>,
+    ids: HashMap<String, u32>,
+    closed: bool,
+}
+
+impl LabelSet {
+    /// Parses `--labels negative,positive` (ids from 0) or `--labels 0=negative,1=positive`.
+    pub fn parse_spec(spec: &str) -> Result<LabelSet, String> {
+        let mut labels = LabelSet {
+            closed: true,
+            ..LabelSet::default()
+        };
+
+        for (position, entry) in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .enumerate()
+        {
+            let (id, name) = match entry.split_once('=') {
+                Some((id, name)) => (
+                    id.trim()
+                        .parse()
+                        .map_err(|e| format!("label id `{}`: {}", id, e))?,
+                    name.trim(),
+                ),
+                None => (position as u32, entry),
+            };
+            if labels.names.contains_key(&id) || labels.ids.contains_key(name) {
+                return Err(format!("label `{}` ({}) is declared twice", name, id));
+            }
+            labels.insert(id, name);
+        }
+
+        Ok(labels)
+    }
+
+    fn insert(&mut self, id: u32, name: &str) {
+        self.names.insert(id, name.to_string());
+        self.ids.insert(name.to_string(), id);
+    }
+
+    /// Id and name of a raw label value, which can be either.
+    pub fn resolve(&mut self, raw: &str) -> Result<(u32, String), String> {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return Err("empty label".to_string());
+        }
+        if let Some(id) = self.ids.get(raw) {
+            return Ok((*id, raw.to_string()));
+        }
+        if let Ok(id) = raw.parse::<u32>() {
+            if let Some(name) = self.names.get(&id) {
+                return Ok((id, name.clone()));
+            }
+        }
+        if self.closed {
+            return Err(format!("unknown label `{}`", raw));
+        }
+
+        let numeric = |(id, name): (&u32, &String)| *name == id.to_string();
+        let id = match raw.parse::<u32>() {
+            Ok(_) if !self.names.iter().all(numeric) => {
+                return Err(format!(
+                    "label `{}` is a number, but the labels so far are names",
+                    raw
+                ))
+            }
+            Ok(id) => id,
+            Err(_) if self.names.iter().any(numeric) => {
+                return Err(format!(
+                    "label `{}` is a name, but the labels so far are numbers",
+                    raw
+                ))
+            }
+            Err(_) => self.names.keys().next_back().map_or(0, |last| last + 1),
+        };
+        self.insert(id, raw);
+        Ok((id, raw.to_string()))
+    }
+
+    /// Id of a label name, without adding it.
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    pub fn names(&self) -> &BTreeMap<u32, String> {
+        &self.names
+    }
+
+    pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS labels ( id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE )",
+            (),
+        )?;
+        Ok(())
+    }
+
+    /// Stores every label in the `labels` table, keeping the ones already there. A label whose
+    /// id or name is stored with a different name or id is an error.
+    pub fn save(&self, conn: &Connection) -> Result<(), SaveError> {
+        LabelSet::create_table(conn)?;
+        let mut stmt_find =
+            conn.prepare("SELECT id, name FROM labels WHERE id = ?1 OR name = ?2")?;
+        let mut stmt_insert = conn.prepare("INSERT INTO labels (id, name) VALUES (?1, ?2)")?;
+        for (id, name) in &self.names {
+            let stored: Vec<(u32, String)> = stmt_find
+                .query_map(params![id, name], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            if let Some((stored_id, stored_name)) = stored
+                .iter()
+                .find(|(stored_id, stored_name)| stored_id != id || stored_name != name)
+            {
+                return Err(SaveError::Conflict(format!(
+                    "label `{}` ({}) conflicts with the stored label `{}` ({})",
+                    name, id, stored_name, stored_id
+                )));
+            }
+            if stored.is_empty() {
+                stmt_insert.execute(params![id, name])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores the labels `load` seeds from the records of a database built before labels had
+    /// names, so the table lists them from then on.
+    pub fn save_seeded(conn: &Connection) -> rusqlite::Result<()> {
+        LabelSet::create_table(conn)?;
+        let mut stmt = conn.prepare("INSERT OR IGNORE INTO labels (id, name) VALUES (?1, ?2)")?;
+        for (id, name) in LabelSet::load(conn)?.names() {
+            stmt.execute(params![id, name])?;
+        }
+        Ok(())
+    }
+
+    /// Reads the `labels` table. Databases built before labels had names have no labels stored,
+    /// their set is seeded from the numeric labels of the records instead.
+    pub fn load(conn: &Connection) -> rusqlite::Result<LabelSet> {
+        let mut labels = LabelSet::default();
+        if has_table(conn, "labels")? {
+            let mut stmt = conn.prepare("SELECT id, name FROM labels")?;
+            let rows = stmt.query_map((), |row| {
+                Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (id, name) = row?;
+                labels.insert(id, &name);
+            }
+        }
+        if !labels.names.is_empty() || !has_table(conn, "records")? {
+            return Ok(labels);
+        }
+
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT CAST(label AS TEXT) FROM records WHERE label IS NOT NULL")?;
+        let rows = stmt.query_map((), |row| row.get::<_, String>(0))?;
+        for row in rows {
+            if let Ok(id) = row?.trim().parse::<u32>() {
+                labels.insert(id, &id.to_string());
+            }
+        }
+        Ok(labels)
+    }
+}
+
+fn has_table(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [name],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Why `LabelSet::save` failed.
+#[derive(Debug)]
+pub enum SaveError {
+    /// A label that is stored with another id or name.
+    Conflict(String),
+    Database(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for SaveError {
+    fn from(e: rusqlite::Error) -> Self {
+        SaveError::Database(e)
+    }
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Conflict(reason) => f.write_str(reason),
+            SaveError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE records (id INTEGER PRIMARY KEY NOT NULL, title TEXT, text TEXT, label TEXT);
+             INSERT INTO records VALUES (1, 'a', 'a', '1'), (2, 'b', 'b', '0'), (3, 'c', 'c', '1');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn legacy_databases_are_seeded_from_the_records() {
+        let conn = legacy_database();
+
+        let mut labels = LabelSet::load(&conn).unwrap();
+        assert_eq!(labels.resolve("1"), Ok((1, "1".to_string())));
+        assert!(labels.resolve("positive").is_err());
+
+        LabelSet::create_table(&conn).unwrap();
+        assert_eq!(LabelSet::load(&conn).unwrap().name(0), Some("0"));
+    }
+
+    #[test]
+    fn seeded_labels_are_stored_once() {
+        let conn = legacy_database();
+        LabelSet::save_seeded(&conn).unwrap();
+        LabelSet::save_seeded(&conn).unwrap();
+
+        let stored: Vec<(u32, String)> = conn
+            .prepare("SELECT id, name FROM labels ORDER BY id")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(stored, vec![(0, "0".to_string()), (1, "1".to_string())]);
+    }
+}
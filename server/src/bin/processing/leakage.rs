@@ -0,0 +1,171 @@
+use rand::{rngs::StdRng, SeedableRng};
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::Path,
+};
+
+use super::{
+    cli::{self, Args},
+    create_shingles, generate_hash_funcs, generate_minhash_signature, jaccard, load_data,
+    loader::LoadOptions,
+    lsh::LshIndex,
+    minhash_similarity,
+    normalize::Normalization,
+    seed_from_args, Record, DEFAULT_SIMILARITY_THRESHOLD,
+};
+
+/// A record from the probed split that is a near-duplicate of one in the indexed split.
+#[derive(Debug, serde::Serialize, Clone)]
+pub struct LeakedPair {
+    pub indexed_id: u32,
+    pub probe_id: u32,
+    pub similarity: f64,
+    pub jaccard: f64,
+}
+
+/// Text used to compare records across splits. Ids and labels are left out since they are
+/// assigned per file and would only add noise.
+fn content(record: &Record) -> String {
+    format!("{} {}", record.title, record.text)
+}
+
+/// Indexes `indexed` with LSH over MinHash signatures and probes it with every record in
+/// `probe`, returning each cross-split pair whose estimated similarity is at least `threshold`.
+pub fn find_leakage(
+    indexed: &HashMap<u32, Record>,
+    probe: &HashMap<u32, Record>,
+    shingle_size: usize,
+    minhash_length: usize,
+    bands: usize,
+    seed: u64,
+    threshold: f64,
+) -> Vec<LeakedPair> {
+    let hash_funcs = generate_hash_funcs(minhash_length, &mut StdRng::seed_from_u64(seed));
+
+    let indexed_shingles = create_shingles(
+        &indexed.iter().map(|(id, r)| (*id, content(r))).collect(),
+        shingle_size,
+    );
+    let probe_shingles = create_shingles(
+        &probe.iter().map(|(id, r)| (*id, content(r))).collect(),
+        shingle_size,
+    );
+
+    let indexed_signatures: HashMap<u32, Vec<u64>> = indexed_shingles
+        .par_iter()
+        .map(|(id, shingles)| (*id, generate_minhash_signature(shingles, &hash_funcs)))
+        .collect();
+
+    let mut lsh = LshIndex::for_signature_length(minhash_length, bands);
+    for (id, signature) in &indexed_signatures {
+        lsh.insert(*id, signature);
+    }
+
+    let mut leaked: Vec<LeakedPair> = probe_shingles
+        .par_iter()
+        .flat_map_iter(|(probe_id, shingles)| {
+            let signature = generate_minhash_signature(shingles, &hash_funcs);
+
+            lsh.candidates(&signature)
+                .into_iter()
+                .filter_map(|indexed_id| {
+                    let similarity =
+                        minhash_similarity(&signature, &indexed_signatures[&indexed_id]);
+                    (similarity >= threshold).then(|| LeakedPair {
+                        indexed_id,
+                        probe_id: *probe_id,
+                        similarity,
+                        jaccard: jaccard(shingles, &indexed_shingles[&indexed_id]),
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    leaked.sort_by(|a, b| {
+        b.similarity
+            .total_cmp(&a.similarity)
+            .then(a.probe_id.cmp(&b.probe_id))
+            .then(a.indexed_id.cmp(&b.indexed_id))
+    });
+    leaked
+}
+
+pub fn run_leakage(args: &Args) {
+    let (Some(train_file), Some(test_file)) = (args.get("train"), args.get("test")) else {
+        cli::exit_with_usage("leakage needs both --train and --test");
+    };
+    let threshold = args.parse_or("min-similarity", DEFAULT_SIMILARITY_THRESHOLD);
+    let shingle_size = args.parse_or("shingle-size", 3);
+    let minhash_length = args.parse_or("minhash-length", 20);
+    let bands = args.parse_or("bands", 5);
+    let seed = seed_from_args(args);
+    let load_options = LoadOptions::from_args(args);
+    let normalization = args.parse_or("normalize", Normalization::default());
+
+    println!("Loading train split...");
+    let (train, _) = load_data(Path::new(train_file), &load_options);
+    println!("Loading test split...");
+    let (test, _) = load_data(Path::new(test_file), &load_options);
+
+    let normalize = |records: &HashMap<u32, Record>| -> HashMap<u32, Record> {
+        records
+            .iter()
+            .map(|(id, record)| (*id, normalization.record(record)))
+            .collect()
+    };
+
+    println!("Indexing train split and probing with test split...");
+    let leaked = find_leakage(
+        &normalize(&train),
+        &normalize(&test),
+        shingle_size,
+        minhash_length,
+        bands,
+        seed,
+        threshold,
+    );
+
+    println!(
+        "{} leaked pairs (similarity >= {}) touching {} test records",
+        leaked.len(),
+        threshold,
+        leaked
+            .iter()
+            .map(|pair| pair.probe_id)
+            .collect::<HashSet<_>>()
+            .len()
+    );
+    for pair in &leaked {
+        println!(
+            "{:.3} (jaccard {:.3})  test #{} -> train #{}  {}",
+            pair.similarity,
+            pair.jaccard,
+            pair.probe_id,
+            pair.indexed_id,
+            test[&pair.probe_id].title
+        );
+    }
+
+    if let Some(output) = args.get("output") {
+        let file = File::create(output).expect("Failed to create file");
+        let mut writer = csv::Writer::from_writer(file);
+        writer
+            .write_record(["test_id", "train_id", "similarity", "jaccard"])
+            .expect("Failed to write header");
+        for pair in &leaked {
+            writer
+                .write_record(&[
+                    pair.probe_id.to_string(),
+                    pair.indexed_id.to_string(),
+                    pair.similarity.to_string(),
+                    pair.jaccard.to_string(),
+                ])
+                .expect("Failed to write record");
+        }
+        writer.flush().expect("Failed to flush writer");
+        println!("Wrote leaked pairs to {}", output);
+    }
+}
@@ -0,0 +1,409 @@
+use rusqlite::{params, Connection};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    path::Path,
+};
+
+use super::{
+    cli::Args,
+    input::{open_rows, InputFormat, InputOptions, RowReader},
+    labels::LabelSet,
+    metadata::{self, Metadata, MetadataType},
+    Record,
+};
+
+/// What to do with a row that does not map onto a `Record`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop the run at the first bad row.
+    Fail,
+    /// Drop the row and keep going.
+    Skip,
+    /// Drop the row and write it to the rejects file.
+    Quarantine,
+}
+
+impl std::str::FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnError::Fail),
+            "skip" => Ok(OnError::Skip),
+            "quarantine" => Ok(OnError::Quarantine),
+            _ => Err(format!("unknown error policy `{}`", s)),
+        }
+    }
+}
+
+/// What to do with a row whose id was already loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDuplicateId {
+    /// Keep the first row with the id and drop the later ones.
+    First,
+    /// Keep the last row with the id (only possible when the whole file is loaded at once).
+    Last,
+    /// Stop the run.
+    Fail,
+    /// Keep the first row and write the later ones to the rejects file.
+    Quarantine,
+}
+
+impl std::str::FromStr for OnDuplicateId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(OnDuplicateId::First),
+            "last" => Ok(OnDuplicateId::Last),
+            "fail" => Ok(OnDuplicateId::Fail),
+            "quarantine" => Ok(OnDuplicateId::Quarantine),
+            _ => Err(format!("unknown duplicate id policy `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub on_error: OnError,
+    pub on_duplicate_id: OnDuplicateId,
+    pub rejects_path: String,
+    pub input: InputOptions,
+}
+
+impl LoadOptions {
+    pub fn from_args(args: &Args) -> LoadOptions {
+        LoadOptions {
+            on_error: args.parse_or("on-error", OnError::Fail),
+            on_duplicate_id: args.parse_or("on-duplicate-id", OnDuplicateId::First),
+            rejects_path: args.get_or("rejects", "rejects.csv"),
+            input: InputOptions::from_args(args),
+        }
+    }
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            on_error: OnError::Fail,
+            on_duplicate_id: OnDuplicateId::First,
+            rejects_path: "rejects.csv".to_string(),
+            input: InputOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LoadSummary {
+    pub rows: usize,
+    pub loaded: usize,
+    pub malformed: usize,
+    pub duplicate_ids: usize,
+    pub quarantined: usize,
+}
+
+impl LoadSummary {
+    pub fn print(&self, options: &LoadOptions) {
+        println!(
+            "read {} rows: {} loaded, {} malformed, {} duplicate ids",
+            self.rows, self.loaded, self.malformed, self.duplicate_ids
+        );
+        if self.quarantined > 0 {
+            println!(
+                "{} rows written to {}",
+                self.quarantined, options.rejects_path
+            );
+        }
+    }
+}
+
+/// A row that stops the run under `--on-error fail` or `--on-duplicate-id fail`.
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    pub line: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// Ids of the rows a loader has yielded, to spot rows that repeat one.
+enum SeenIds {
+    Memory(HashSet<u32>),
+    /// A table in a private on-disk SQLite database, for chunked runs whose memory use must not
+    /// grow with the size of the input.
+    Disk(Connection),
+}
+
+impl SeenIds {
+    fn on_disk() -> rusqlite::Result<SeenIds> {
+        // an empty path is a temporary database, deleted when the connection closes
+        let conn = Connection::open("")?;
+        conn.execute("CREATE TABLE seen_ids ( id INTEGER PRIMARY KEY )", ())?;
+        Ok(SeenIds::Disk(conn))
+    }
+
+    /// `true` the first time `id` is seen.
+    fn insert(&mut self, id: u32) -> bool {
+        match self {
+            SeenIds::Memory(ids) => ids.insert(id),
+            SeenIds::Disk(conn) => {
+                conn.prepare_cached("INSERT OR IGNORE INTO seen_ids VALUES (?1)")
+                    .and_then(|mut stmt| stmt.execute(params![id]))
+                    .expect("Failed to track record ids")
+                    == 1
+            }
+        }
+    }
+}
+
+/// Reads `Record`s one at a time, applying the error and duplicate id policies.
+///
+/// Rows are read as raw fields first, by the reader for the input format, and mapped onto
+/// `Record`s through the column mapping, so a bad row can be reported with its line number,
+/// reason and content instead of aborting the whole run. Under the `fail` policies the bad row
+/// is yielded as an error and the caller decides how to end the run.
+pub struct RecordLoader {
+    rows: RowReader,
+    options: LoadOptions,
+    next_id: u32,
+    rejects: Option<csv::Writer<File>>,
+    seen_ids: SeenIds,
+    pub summary: LoadSummary,
+    /// Labels seen so far, or the ones given with `--labels`.
+    pub labels: LabelSet,
+}
+
+impl RecordLoader {
+    pub fn open(file_path: &Path, options: &LoadOptions) -> RecordLoader {
+        let mut options = options.clone();
+        // text files carry no id of their own
+        if options.input.format_for(file_path) == InputFormat::TextDir {
+            options.input.generate_ids = true;
+        }
+
+        RecordLoader {
+            rows: open_rows(file_path, &options.input),
+            next_id: 1,
+            rejects: None,
+            seen_ids: SeenIds::Memory(HashSet::new()),
+            summary: LoadSummary::default(),
+            labels: options.input.labels.clone().unwrap_or_default(),
+            options,
+        }
+    }
+
+    /// Generated ids start at `id` instead of 1, for inputs appended to an existing database.
+    pub fn starting_at_id(mut self, id: u32) -> RecordLoader {
+        self.next_id = id;
+        self
+    }
+
+    /// Remembers the ids seen so far on disk rather than in memory, for inputs read in chunks.
+    pub fn tracking_ids_on_disk(mut self) -> RecordLoader {
+        self.seen_ids = SeenIds::on_disk().expect("Failed to create the table of seen ids");
+        self
+    }
+
+    /// Maps the fields of a row onto a `Record`, numbering it when ids are generated.
+    fn map_row(&mut self, fields: &HashMap<String, String>) -> Result<Record, String> {
+        let columns = &self.options.input.columns;
+        let field = |name: &str, column: &str| {
+            fields
+                .get(column)
+                .cloned()
+                .ok_or_else(|| format!("missing column `{}` for {}", column, name))
+        };
+        let title = field("title", &columns.title)?;
+        let text = field("text", &columns.text)?;
+        let raw_label = match &self.options.input.default_label {
+            Some(default) if !fields.contains_key(&columns.label) => default.clone(),
+            _ => field("label", &columns.label)?,
+        };
+        let id = if self.options.input.generate_ids {
+            self.next_id
+        } else {
+            field("id", &columns.id)?
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| format!("id `{}`: {}", fields[&columns.id], e))?
+        };
+
+        let metadata = self.metadata(fields)?;
+        let (label, label_name) = self.labels.resolve(&raw_label)?;
+
+        if self.options.input.generate_ids {
+            self.next_id += 1;
+        }
+        Ok(Record {
+            id,
+            title,
+            text,
+            label,
+            label_name: Some(label_name),
+            metadata,
+        })
+    }
+
+    /// Typed metadata of a row. Empty values are left out.
+    fn metadata(&self, fields: &HashMap<String, String>) -> Result<Metadata, String> {
+        let columns = &self.options.input.columns;
+        let mut metadata = Metadata::new();
+
+        match &self.options.input.metadata {
+            Some(spec) => {
+                for (name, kind) in spec {
+                    if let Some(raw) = fields.get(name).filter(|raw| !raw.trim().is_empty()) {
+                        let value = kind
+                            .parse(raw)
+                            .map_err(|e| format!("metadata `{}`: {}", name, e))?;
+                        metadata.insert(name.clone(), value);
+                    }
+                }
+            }
+            None => {
+                let core = [&columns.id, &columns.title, &columns.text, &columns.label];
+                for (name, raw) in fields {
+                    if !core.contains(&name) && !raw.trim().is_empty() {
+                        metadata::check_field_name(name)?;
+                        metadata.insert(name.clone(), MetadataType::Text.parse(raw)?);
+                    }
+                }
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Up to `size` records, or the row that stopped the run.
+    pub fn take_chunk(&mut self, size: usize) -> Result<Vec<Record>, LoadError> {
+        self.by_ref().take(size).collect()
+    }
+
+    fn reject(&mut self, line: u64, reason: &str, content: &str) -> Result<(), LoadError> {
+        match self.options.on_error {
+            OnError::Fail => {
+                return Err(LoadError {
+                    line,
+                    reason: reason.to_string(),
+                })
+            }
+            OnError::Skip => {}
+            OnError::Quarantine => self.quarantine(line, reason, content),
+        }
+        Ok(())
+    }
+
+    fn quarantine(&mut self, line: u64, reason: &str, content: &str) {
+        let rejects_path = &self.options.rejects_path;
+        let rejects = self.rejects.get_or_insert_with(|| {
+            let file = File::create(rejects_path).expect("Failed to create rejects file");
+            let mut writer = csv::Writer::from_writer(file);
+            writer
+                .write_record(["line", "reason", "content"])
+                .expect("Failed to write header");
+            writer
+        });
+        rejects
+            .write_record([line.to_string().as_str(), reason, content])
+            .expect("Failed to write rejected row");
+        rejects.flush().expect("Failed to flush rejects file");
+        self.summary.quarantined += 1;
+    }
+
+    /// `true` if `record` should be kept. With `OnDuplicateId::Last` every row is kept and the
+    /// caller is expected to let later rows overwrite earlier ones.
+    fn check_duplicate(
+        &mut self,
+        record: &Record,
+        line: u64,
+        content: &str,
+    ) -> Result<bool, LoadError> {
+        if self.seen_ids.insert(record.id) {
+            return Ok(true);
+        }
+
+        self.summary.duplicate_ids += 1;
+        let reason = format!("duplicate id {}", record.id);
+        match self.options.on_duplicate_id {
+            OnDuplicateId::First => Ok(false),
+            OnDuplicateId::Last => Ok(true),
+            OnDuplicateId::Fail => Err(LoadError { line, reason }),
+            OnDuplicateId::Quarantine => {
+                self.quarantine(line, &reason, content);
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Iterator for RecordLoader {
+    type Item = Result<Record, LoadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let row = self.rows.next()?;
+            self.summary.rows += 1;
+
+            match row.fields.and_then(|fields| self.map_row(&fields)) {
+                Ok(record) => match self.check_duplicate(&record, row.line, &row.content) {
+                    Ok(true) => {
+                        self.summary.loaded += 1;
+                        return Some(Ok(record));
+                    }
+                    Ok(false) => {}
+                    Err(e) => return Some(Err(e)),
+                },
+                Err(reason) => {
+                    self.summary.malformed += 1;
+                    if let Err(e) = self.reject(row.line, &reason, &row.content) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{record, write_input, ScratchDir};
+    use super::*;
+
+    #[test]
+    fn ids_tracked_on_disk_catch_repeats_across_chunks() {
+        let dir = ScratchDir::new("loader-seen-ids");
+        let input = dir.join("input.csv");
+        let records: Vec<Record> = [1, 2, 3, 2, 4, 1]
+            .iter()
+            .map(|id| record(*id, "title", "text", 0))
+            .collect();
+        write_input(&input, &records);
+
+        let mut loader = RecordLoader::open(&input, &LoadOptions::default()).tracking_ids_on_disk();
+        let first: Vec<u32> = loader.take_chunk(3).unwrap().iter().map(|r| r.id).collect();
+        let second: Vec<u32> = loader.take_chunk(3).unwrap().iter().map(|r| r.id).collect();
+        assert_eq!((first, second), (vec![1, 2, 3], vec![4]));
+        assert_eq!(loader.summary.duplicate_ids, 2);
+    }
+
+    #[test]
+    fn metadata_columns_named_after_record_fields_are_rejected() {
+        let dir = ScratchDir::new("loader-record-fields");
+        let input = dir.join("input.csv");
+        std::fs::write(&input, "id;title;text;label;length\n1;a;b;0;12\n").unwrap();
+
+        let error = RecordLoader::open(&input, &LoadOptions::default())
+            .take_chunk(1)
+            .unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(error.reason.contains("`length`"), "{}", error.reason);
+
+        assert!(metadata::parse_spec("author,duplicates:int").is_err());
+        assert!(metadata::parse_spec("author,year:int").is_ok());
+    }
+}
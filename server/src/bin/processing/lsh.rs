@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet};
+
+use super::stable_hash;
+
+/// Locality sensitive hashing over MinHash signatures.
+///
+/// Each signature is cut into `bands` slices of `rows` values. Documents that share the exact
+/// same slice in at least one band land in the same bucket and become candidates for each
+/// other, so only a fraction of all pairs has to be compared.
+pub struct LshIndex {
+    bands: usize,
+    rows: usize,
+    buckets: Vec<HashMap<u64, Vec<u32>>>,
+}
+
+impl LshIndex {
+    pub fn new(bands: usize, rows: usize) -> LshIndex {
+        LshIndex {
+            bands,
+            rows,
+            buckets: (0..bands).map(|_| HashMap::new()).collect(),
+        }
+    }
+
+    /// Picks the largest band count (at most `max_bands`) that evenly divides the signature.
+    pub fn for_signature_length(signature_length: usize, max_bands: usize) -> LshIndex {
+        let bands = (1..=max_bands.clamp(1, signature_length.max(1)))
+            .rev()
+            .find(|bands| signature_length.is_multiple_of(*bands))
+            .unwrap_or(1);
+
+        LshIndex::new(bands, signature_length / bands)
+    }
+
+    pub fn bands(&self) -> usize {
+        self.bands
+    }
+
+    /// One bucket key per band of `signature`. Keys are stored in `lsh_buckets`, so they have to
+    /// stay the same across builds.
+    pub fn band_keys<'a>(&'a self, signature: &'a [u64]) -> impl Iterator<Item = u64> + 'a {
+        signature.chunks(self.rows).take(self.bands).map(|band| {
+            let bytes: Vec<u8> = band.iter().flat_map(|value| value.to_le_bytes()).collect();
+            stable_hash(&bytes)
+        })
+    }
+
+    pub fn insert(&mut self, doc_id: u32, signature: &[u64]) {
+        let keys: Vec<u64> = self.band_keys(signature).collect();
+        for (band, key) in keys.into_iter().enumerate() {
+            self.buckets[band].entry(key).or_default().push(doc_id);
+        }
+    }
+
+    /// Every indexed document sharing at least one band with `signature`.
+    pub fn candidates(&self, signature: &[u64]) -> HashSet<u32> {
+        let mut candidates = HashSet::new();
+        for (band, key) in self.band_keys(signature).enumerate() {
+            if let Some(bucket) = self.buckets[band].get(&key) {
+                candidates.extend(bucket);
+            }
+        }
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands_evenly_divide_the_signature() {
+        let index = LshIndex::for_signature_length(20, 5);
+        assert_eq!((index.bands, index.rows), (5, 4));
+
+        let index = LshIndex::for_signature_length(20, 3);
+        assert_eq!((index.bands, index.rows), (2, 10));
+
+        let index = LshIndex::for_signature_length(7, 5);
+        assert_eq!((index.bands, index.rows), (1, 7));
+    }
+
+    #[test]
+    fn candidates_share_a_band() {
+        let mut index = LshIndex::new(4, 2);
+        index.insert(1, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        index.insert(2, &[1, 2, 0, 0, 0, 0, 0, 0]);
+        index.insert(3, &[9, 9, 9, 9, 9, 9, 9, 9]);
+
+        let candidates = index.candidates(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(candidates, HashSet::from([1, 2]));
+        // one value off in every band
+        assert!(index.candidates(&[1, 0, 3, 0, 5, 0, 7, 0]).is_empty());
+    }
+
+    #[test]
+    fn band_keys_are_stable() {
+        // keys stored in `lsh_buckets` by earlier builds must still match
+        let index = LshIndex::new(2, 2);
+        let keys: Vec<u64> = index.band_keys(&[1, 2, 3, 4]).collect();
+        assert_eq!(keys, vec![0x7717_9803_63c8_e066, 0x5b33_195d_4857_5422]);
+    }
+}
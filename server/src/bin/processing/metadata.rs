@@ -0,0 +1,474 @@
+use rusqlite::{params, types::Value, Connection, OptionalExtension};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+};
+
+use super::{labels::LabelSet, Record};
+
+/// Type of a metadata field, declared with `--metadata field:type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataType {
+    Text,
+    Integer,
+    Float,
+    Bool,
+    /// `YYYY-MM-DD`, stored as text so it sorts chronologically.
+    Date,
+}
+
+impl std::str::FromStr for MetadataType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" | "string" => Ok(MetadataType::Text),
+            "int" | "integer" => Ok(MetadataType::Integer),
+            "float" | "number" => Ok(MetadataType::Float),
+            "bool" => Ok(MetadataType::Bool),
+            "date" => Ok(MetadataType::Date),
+            _ => Err(format!("unknown metadata type `{}`", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for MetadataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MetadataType::Text => "text",
+            MetadataType::Integer => "int",
+            MetadataType::Float => "float",
+            MetadataType::Bool => "bool",
+            MetadataType::Date => "date",
+        })
+    }
+}
+
+impl MetadataType {
+    /// Parses a raw field value into this type.
+    pub fn parse(&self, raw: &str) -> Result<MetadataValue, String> {
+        let raw = raw.trim();
+        match self {
+            MetadataType::Text => Ok(MetadataValue::Text(raw.to_string())),
+            MetadataType::Integer => raw
+                .parse()
+                .map(MetadataValue::Integer)
+                .map_err(|e| format!("`{}` is not an integer: {}", raw, e)),
+            MetadataType::Float => raw
+                .parse()
+                .map(MetadataValue::Float)
+                .map_err(|e| format!("`{}` is not a number: {}", raw, e)),
+            MetadataType::Bool => match raw.to_lowercase().as_str() {
+                "true" | "1" | "yes" => Ok(MetadataValue::Bool(true)),
+                "false" | "0" | "no" => Ok(MetadataValue::Bool(false)),
+                _ => Err(format!("`{}` is not a boolean", raw)),
+            },
+            MetadataType::Date => {
+                if is_date(raw) {
+                    Ok(MetadataValue::Date(raw.to_string()))
+                } else {
+                    Err(format!("`{}` is not a YYYY-MM-DD date", raw))
+                }
+            }
+        }
+    }
+}
+
+fn is_date(raw: &str) -> bool {
+    let parts: Vec<&str> = raw.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return false;
+    };
+    let number = |part: &str, len: usize| {
+        (part.len() == len && part.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| part.parse::<u32>().ok())
+            .flatten()
+    };
+
+    number(year, 4).is_some()
+        && number(month, 2).is_some_and(|month| (1..=12).contains(&month))
+        && number(day, 2).is_some_and(|day| (1..=31).contains(&day))
+}
+
+/// Value of one metadata field. Serialized as a plain json string, number or boolean. The type
+/// of a stored value comes from its field in `metadata_fields`, json only tells text, numbers
+/// and booleans apart.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(untagged)]
+pub enum MetadataValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    Date(String),
+}
+
+impl<'de> serde::Deserialize<'de> for MetadataValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        MetadataValue::from_json(&value).ok_or_else(|| {
+            serde::de::Error::custom("metadata values must be a string, number or boolean")
+        })
+    }
+}
+
+impl MetadataValue {
+    /// Reads a plain json value. Json has no dates, so a date string is text unless its field
+    /// is declared as a date.
+    pub fn from_json(value: &serde_json::Value) -> Option<MetadataValue> {
+        match value {
+            serde_json::Value::Bool(value) => Some(MetadataValue::Bool(*value)),
+            serde_json::Value::Number(value) => value
+                .as_i64()
+                .map(MetadataValue::Integer)
+                .or_else(|| value.as_f64().map(MetadataValue::Float)),
+            serde_json::Value::String(value) => Some(MetadataValue::Text(value.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> MetadataType {
+        match self {
+            MetadataValue::Text(_) => MetadataType::Text,
+            MetadataValue::Integer(_) => MetadataType::Integer,
+            MetadataValue::Float(_) => MetadataType::Float,
+            MetadataValue::Bool(_) => MetadataType::Bool,
+            MetadataValue::Date(_) => MetadataType::Date,
+        }
+    }
+
+    fn to_sql(&self) -> Value {
+        match self {
+            MetadataValue::Text(value) | MetadataValue::Date(value) => Value::Text(value.clone()),
+            MetadataValue::Integer(value) => Value::Integer(*value),
+            MetadataValue::Float(value) => Value::Real(*value),
+            MetadataValue::Bool(value) => Value::Integer(i64::from(*value)),
+        }
+    }
+
+    fn from_sql(kind: MetadataType, value: Value) -> Option<MetadataValue> {
+        match (kind, value) {
+            (MetadataType::Text, Value::Text(value)) => Some(MetadataValue::Text(value)),
+            (MetadataType::Date, Value::Text(value)) => Some(MetadataValue::Date(value)),
+            (MetadataType::Integer, Value::Integer(value)) => Some(MetadataValue::Integer(value)),
+            (MetadataType::Float, Value::Real(value)) => Some(MetadataValue::Float(value)),
+            (MetadataType::Float, Value::Integer(value)) => {
+                Some(MetadataValue::Float(value as f64))
+            }
+            (MetadataType::Bool, Value::Integer(value)) => Some(MetadataValue::Bool(value != 0)),
+            _ => None,
+        }
+    }
+
+    /// Orders two values of the same type, `None` for values of different types.
+    pub fn compare(&self, other: &MetadataValue) -> Option<Ordering> {
+        match (self, other) {
+            (MetadataValue::Text(a), MetadataValue::Text(b))
+            | (MetadataValue::Date(a), MetadataValue::Date(b)) => Some(a.cmp(b)),
+            (MetadataValue::Integer(a), MetadataValue::Integer(b)) => Some(a.cmp(b)),
+            (MetadataValue::Float(a), MetadataValue::Float(b)) => a.partial_cmp(b),
+            (MetadataValue::Bool(a), MetadataValue::Bool(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata fields of a record, by name.
+pub type Metadata = BTreeMap<String, MetadataValue>;
+
+/// Which source columns become metadata. `None` keeps every column that isn't mapped onto a core
+/// record field, as text.
+pub type MetadataSpec = Option<Vec<(String, MetadataType)>>;
+
+/// Parses `--metadata author,year:int,published:date`. Fields without a type are text.
+pub fn parse_spec(spec: &str) -> Result<Vec<(String, MetadataType)>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (name, kind) = match field.split_once(':') {
+                Some((name, kind)) => (name.trim(), kind.trim().parse()?),
+                None => (field, MetadataType::Text),
+            };
+            check_field_name(name)?;
+            Ok((name.to_string(), kind))
+        })
+        .collect()
+}
+
+/// Fields filters and sorts read from the record itself, see `field_value`.
+pub const RECORD_FIELDS: [&str; 5] = ["id", "title", "label", "length", DUPLICATES_FIELD];
+
+/// A metadata field named after a record field could never be filtered or sorted on.
+pub fn check_field_name(name: &str) -> Result<(), String> {
+    match RECORD_FIELDS.contains(&name) {
+        true => Err(format!(
+            "metadata field `{}` has the name of a record field",
+            name
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Creates the metadata tables:
+///
+/// - `metadata_fields`: name and type of every field seen so far
+/// - `record_metadata`: one row per (record, field), values stored with their sqlite type
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS metadata_fields ( name TEXT PRIMARY KEY, type TEXT NOT NULL );
+         CREATE TABLE IF NOT EXISTS record_metadata (
+             record_id INTEGER NOT NULL,
+             field TEXT NOT NULL,
+             value,
+             PRIMARY KEY (record_id, field)
+         );",
+    )
+}
+
+/// Stores the metadata of `records`, declaring any field not seen before.
+pub fn save<'a>(
+    conn: &Connection,
+    records: impl IntoIterator<Item = &'a Record>,
+) -> rusqlite::Result<()> {
+    create_tables(conn)?;
+    let mut stmt_field =
+        conn.prepare("INSERT OR IGNORE INTO metadata_fields (name, type) VALUES (?1, ?2)")?;
+    let mut stmt_value = conn.prepare(
+        "INSERT OR REPLACE INTO record_metadata (record_id, field, value) VALUES (?1, ?2, ?3)",
+    )?;
+
+    for record in records {
+        for (field, value) in &record.metadata {
+            stmt_field.execute(params![field, value.kind().to_string()])?;
+            stmt_value.execute(params![record.id, field, value.to_sql()])?;
+        }
+    }
+    Ok(())
+}
+
+/// Declared metadata fields of a database, empty for databases built without metadata.
+pub fn fields(conn: &Connection) -> rusqlite::Result<HashMap<String, MetadataType>> {
+    let has_table = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata_fields'",
+            (),
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    if !has_table {
+        return Ok(HashMap::new());
+    }
+
+    let mut stmt = conn.prepare("SELECT name, type FROM metadata_fields")?;
+    let fields = stmt
+        .query_map((), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .filter_map(|row| {
+            let (name, kind) = row.ok()?;
+            Some((name, kind.parse().ok()?))
+        })
+        .collect();
+    Ok(fields)
+}
+
+/// Reads the metadata of several records in one query.
+pub fn load_many(
+    conn: &Connection,
+    fields: &HashMap<String, MetadataType>,
+    record_ids: &[u32],
+) -> rusqlite::Result<HashMap<u32, Metadata>> {
+    let mut metadata: HashMap<u32, Metadata> = HashMap::new();
+    if fields.is_empty() || record_ids.is_empty() {
+        return Ok(metadata);
+    }
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT record_id, field, value FROM record_metadata
+         WHERE record_id IN (SELECT value FROM json_each(?1))",
+    )?;
+    let ids = serde_json::to_string(record_ids).expect("ids serialize");
+    let rows = stmt.query_map(params![ids], |row| {
+        Ok((
+            row.get::<_, u32>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Value>(2)?,
+        ))
+    })?;
+
+    for row in rows {
+        let (record_id, field, value) = row?;
+        if let Some(value) = fields
+            .get(&field)
+            .and_then(|kind| MetadataValue::from_sql(*kind, value))
+        {
+            metadata.entry(record_id).or_default().insert(field, value);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Number of near-duplicates of a record. It is kept in the `similarities` table rather than
+/// on the record, so whoever filters or sorts on it sets it in the record's metadata first.
+pub const DUPLICATES_FIELD: &str = "duplicates";
+
+/// Value of a core record field or metadata field, for filtering and sorting.
+pub fn field_value(record: &Record, field: &str) -> Option<MetadataValue> {
+    match field {
+        "id" => Some(MetadataValue::Integer(record.id.into())),
+        "label" => Some(MetadataValue::Integer(record.label.into())),
+        "title" => Some(MetadataValue::Text(record.title.clone())),
+        // characters of the text
+        "length" => Some(MetadataValue::Integer(record.text.chars().count() as i64)),
+        _ => record.metadata.get(field).cloned(),
+    }
+}
+
+/// Type of a field that can be filtered or sorted on.
+fn field_type(fields: &HashMap<String, MetadataType>, field: &str) -> Result<MetadataType, String> {
+    match field {
+        "id" | "label" | "length" | DUPLICATES_FIELD => Ok(MetadataType::Integer),
+        "title" => Ok(MetadataType::Text),
+        _ => fields
+            .get(field)
+            .copied()
+            .ok_or_else(|| format!("unknown field `{}`", field)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// One `field<op>value` condition, e.g. `author=Smith` or `year>=2020`.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: MetadataValue,
+}
+
+impl Filter {
+    /// Records without the field never match.
+    pub fn matches(&self, record: &Record) -> bool {
+        let Some(ordering) =
+            field_value(record, &self.field).and_then(|value| value.compare(&self.value))
+        else {
+            return false;
+        };
+        match self.op {
+            FilterOp::Eq => ordering.is_eq(),
+            FilterOp::Ne => ordering.is_ne(),
+            FilterOp::Lt => ordering.is_lt(),
+            FilterOp::Le => ordering.is_le(),
+            FilterOp::Gt => ordering.is_gt(),
+            FilterOp::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+/// Parses comma separated filters, e.g. `source=reuters,year>=2020`. `:` is accepted for `=`.
+/// `label` takes a name from `labels` as well as an id.
+pub fn parse_filters(
+    spec: &str,
+    fields: &HashMap<String, MetadataType>,
+    labels: &LabelSet,
+) -> Result<Vec<Filter>, String> {
+    const OPS: [(&str, FilterOp); 7] = [
+        ("!=", FilterOp::Ne),
+        (">=", FilterOp::Ge),
+        ("<=", FilterOp::Le),
+        ("=", FilterOp::Eq),
+        (":", FilterOp::Eq),
+        (">", FilterOp::Gt),
+        ("<", FilterOp::Lt),
+    ];
+
+    spec.split(',')
+        .filter(|condition| !condition.trim().is_empty())
+        .map(|condition| {
+            let start = condition
+                .find(['!', '=', '<', '>', ':'])
+                .ok_or_else(|| format!("expected `field<op>value`, found `{}`", condition))?;
+            let (field, rest) = condition.split_at(start);
+            let (op_str, op) = OPS
+                .iter()
+                .find(|(op_str, _)| rest.starts_with(op_str))
+                .ok_or_else(|| format!("unknown operator in `{}`", condition))?;
+
+            let field = field.trim();
+            let kind = field_type(fields, field)?;
+            // text compares character by character, so `year>=2020` on text would be wrong
+            if kind == MetadataType::Text && !matches!(op, FilterOp::Eq | FilterOp::Ne) {
+                return Err(format!(
+                    "`{}` is a text field, only = and != apply to it",
+                    field
+                ));
+            }
+            let raw = &rest[op_str.len()..];
+            let value = match (field, labels.id(raw.trim())) {
+                // labels are stored by id
+                ("label", Some(id)) => MetadataValue::Integer(id.into()),
+                ("label", None) => kind
+                    .parse(raw)
+                    .map_err(|_| format!("unknown label `{}`", raw.trim()))?,
+                _ => kind
+                    .parse(raw)
+                    .map_err(|e| format!("filter on `{}`: {}", field, e))?,
+            };
+            Ok(Filter {
+                field: field.to_string(),
+                op: *op,
+                value,
+            })
+        })
+        .collect()
+}
+
+/// Sort order on one field. Records without the field sort last either way.
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl SortKey {
+    /// Parses `field` (ascending) or `-field` (descending).
+    pub fn parse(spec: &str, fields: &HashMap<String, MetadataType>) -> Result<SortKey, String> {
+        let spec = spec.trim();
+        let (field, descending) = match spec.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (spec, false),
+        };
+        field_type(fields, field)?;
+
+        Ok(SortKey {
+            field: field.to_string(),
+            descending,
+        })
+    }
+
+    pub fn compare(&self, a: &Record, b: &Record) -> Ordering {
+        match (field_value(a, &self.field), field_value(b, &self.field)) {
+            (Some(a), Some(b)) => {
+                let ordering = a.compare(&b).unwrap_or(Ordering::Equal);
+                if self.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use regex::{Captures, Regex};
+use rusqlite::{Connection, OptionalExtension};
+use std::sync::LazyLock;
+use unicode_normalization::UnicodeNormalization;
+
+use super::Record;
+
+static SCRIPT_OR_STYLE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<(script|style)\b.*?</(script|style)\s*>").unwrap());
+static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<!--.*?-->|</?[a-zA-Z][^>]*>").unwrap());
+static ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&(#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]{2,8});").unwrap());
+static EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[\w.+-]+@[\w-]+(\.[\w-]+)+").unwrap());
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\b(https?://|www\.)[^\s<>"]+"#).unwrap());
+
+/// Placeholder tokens masked urls and emails are replaced with.
+pub const URL_MASK: &str = "<url>";
+pub const EMAIL_MASK: &str = "<email>";
+
+/// Text cleanup applied before indexing and shingling. The `records` table keeps the original
+/// text, only the inverse index and similarities see the normalized one.
+///
+/// Steps run in the order of the fields below, so tags are gone before urls are masked and
+/// entities are decoded before Unicode normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Normalization {
+    /// Drop html tags, comments, and `<script>` / `<style>` blocks.
+    pub html: bool,
+    /// Decode html entities such as `&amp;` and `&#8217;`.
+    pub entities: bool,
+    /// Unicode NFKC normalization, which also folds full-width and ligature forms.
+    pub nfkc: bool,
+    /// Replace typographic quotes, dashes and ellipses with their ascii counterparts.
+    pub punctuation: bool,
+    /// Replace email addresses with `<email>`.
+    pub emails: bool,
+    /// Replace urls with `<url>`.
+    pub urls: bool,
+    /// Collapse whitespace runs into single spaces and trim.
+    pub whitespace: bool,
+}
+
+const STEPS: [&str; 7] = [
+    "html",
+    "entities",
+    "nfkc",
+    "punctuation",
+    "emails",
+    "urls",
+    "whitespace",
+];
+
+impl std::str::FromStr for Normalization {
+    type Err = String;
+
+    /// `none`, `all`, or a comma separated list of steps, e.g. `html,entities,whitespace`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut normalization = Normalization::default();
+        for step in s.split(',').map(str::trim).filter(|step| !step.is_empty()) {
+            match step {
+                "none" => {}
+                "all" => {
+                    normalization = Normalization {
+                        html: true,
+                        entities: true,
+                        nfkc: true,
+                        punctuation: true,
+                        emails: true,
+                        urls: true,
+                        whitespace: true,
+                    }
+                }
+                "html" => normalization.html = true,
+                "entities" => normalization.entities = true,
+                "nfkc" => normalization.nfkc = true,
+                "punctuation" => normalization.punctuation = true,
+                "emails" => normalization.emails = true,
+                "urls" => normalization.urls = true,
+                "whitespace" => normalization.whitespace = true,
+                _ => {
+                    return Err(format!(
+                        "unknown normalization step `{}`, expected none, all or {}",
+                        step,
+                        STEPS.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(normalization)
+    }
+}
+
+impl std::fmt::Display for Normalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let enabled = [
+            self.html,
+            self.entities,
+            self.nfkc,
+            self.punctuation,
+            self.emails,
+            self.urls,
+            self.whitespace,
+        ];
+        let steps: Vec<&str> = STEPS
+            .iter()
+            .zip(enabled)
+            .filter(|(_, enabled)| *enabled)
+            .map(|(step, _)| *step)
+            .collect();
+
+        if steps.is_empty() {
+            f.write_str("none")
+        } else {
+            f.write_str(&steps.join(","))
+        }
+    }
+}
+
+impl Normalization {
+    pub fn is_none(&self) -> bool {
+        *self == Normalization::default()
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        if self.is_none() {
+            return text.to_string();
+        }
+
+        let mut text = text.to_string();
+        if self.html {
+            text = SCRIPT_OR_STYLE.replace_all(&text, " ").into_owned();
+            text = TAG.replace_all(&text, " ").into_owned();
+        }
+        if self.entities {
+            text = decode_entities(&text);
+        }
+        if self.nfkc {
+            text = text.nfkc().collect();
+        }
+        if self.punctuation {
+            text = text
+                .chars()
+                .map(|c| match c {
+                    '‘' | '’' | '‚' | '‛' | '′' => "'".to_string(),
+                    '“' | '”' | '„' | '‟' | '″' | '«' | '»' => "\"".to_string(),
+                    '–' | '—' | '―' | '‐' | '‑' => "-".to_string(),
+                    '…' => "...".to_string(),
+                    c => c.to_string(),
+                })
+                .collect();
+        }
+        if self.emails {
+            text = EMAIL.replace_all(&text, EMAIL_MASK).into_owned();
+        }
+        if self.urls {
+            text = URL.replace_all(&text, URL_MASK).into_owned();
+        }
+        if self.whitespace {
+            text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+        }
+        text
+    }
+
+    /// Copy of `record` with its title and text normalized.
+    pub fn record(&self, record: &Record) -> Record {
+        Record {
+            title: self.apply(&record.title),
+            text: self.apply(&record.text),
+            ..record.clone()
+        }
+    }
+
+    /// The normalization a database was built with, none for databases that don't record one.
+    pub fn load(conn: &Connection) -> rusqlite::Result<Normalization> {
+        let has_metadata: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_metadata {
+            return Ok(Normalization::default());
+        }
+
+        let stored: Option<String> = conn
+            .query_row(
+                "SELECT value FROM metadata WHERE key = 'normalization'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(stored
+            .and_then(|stored| stored.parse().ok())
+            .unwrap_or_default())
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    ENTITY
+        .replace_all(text, |captures: &Captures| {
+            let entity = &captures[1];
+            let decoded = if let Some(hex) = entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+            } else if let Some(decimal) = entity.strip_prefix('#') {
+                decimal.parse().ok().and_then(char::from_u32)
+            } else {
+                named_entity(entity)
+            };
+            decoded.map_or_else(|| captures[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+fn named_entity(name: &str) -> Option<char> {
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "sbquo" => '‚',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bdquo" => '„',
+        "laquo" => '«',
+        "raquo" => '»',
+        "hellip" => '…',
+        "bull" => '•',
+        "middot" => '·',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "deg" => '°',
+        "euro" => '€',
+        "pound" => '£',
+        "yen" => '¥',
+        "cent" => '¢',
+        "sect" => '§',
+        "para" => '¶',
+        "times" => '×',
+        "divide" => '÷',
+        _ => return None,
+    })
+}
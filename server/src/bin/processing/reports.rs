@@ -0,0 +1,251 @@
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    cli::Args, labels::LabelSet, parse_similar_documents, Record, DEFAULT_DB_FILE,
+    DEFAULT_SIMILARITY_THRESHOLD,
+};
+
+/// Two near-duplicate records that were given different labels.
+#[derive(Debug, Serialize, Clone)]
+pub struct LabelConflictPair {
+    pub doc_a: Record,
+    pub doc_b: Record,
+    pub similarity: f64,
+}
+
+/// A connected group of near-duplicates that contains more than one label.
+#[derive(Debug, Serialize, Clone)]
+pub struct LabelConflictCluster {
+    pub doc_ids: Vec<u32>,
+    pub label_counts: BTreeMap<u32, usize>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LabelConflictReport {
+    pub min_similarity: f64,
+    pub pairs: Vec<LabelConflictPair>,
+    pub clusters: Vec<LabelConflictCluster>,
+    /// names of the label ids used in `label_counts`
+    pub label_names: BTreeMap<u32, String>,
+}
+
+/// Finds every pair in the `similarities` table at or above `min_similarity` whose labels
+/// disagree, along with the clusters those pairs belong to.
+pub fn label_conflicts(
+    conn: &Connection,
+    min_similarity: f64,
+) -> rusqlite::Result<LabelConflictReport> {
+    let mut labels: HashMap<u32, u32> = HashMap::new();
+    let mut stmt_labels = conn.prepare("SELECT id, label FROM records")?;
+    let label_rows = stmt_labels.query_map([], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in label_rows {
+        let (id, label) = row?;
+        if let Ok(label) = label.parse() {
+            labels.insert(id, label);
+        }
+    }
+
+    // every similar pair above the threshold, stored once with the smaller id first
+    let mut edges: BTreeMap<(u32, u32), f64> = BTreeMap::new();
+    let mut stmt_similarities =
+        conn.prepare("SELECT document_id, similar_documents FROM similarities")?;
+    let similarity_rows = stmt_similarities.query_map([], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in similarity_rows {
+        let (doc_id, raw) = row?;
+        for similar in parse_similar_documents(&raw) {
+            if similar.doc_id == doc_id || similar.similarity < min_similarity {
+                continue;
+            }
+            let key = (doc_id.min(similar.doc_id), doc_id.max(similar.doc_id));
+            let entry = edges.entry(key).or_insert(similar.similarity);
+            *entry = entry.max(similar.similarity);
+        }
+    }
+
+    let label_set = LabelSet::load(conn)?;
+    let mut stmt_record = conn.prepare("SELECT * FROM records WHERE id = (?1)")?;
+    let mut fetch_record = |id: u32| -> rusqlite::Result<Option<Record>> {
+        let mut rows = stmt_record.query_map(params![id], |row| Record::try_from(row))?;
+        let record = rows.next().transpose()?;
+        Ok(record.map(|mut record| {
+            record.label_name = label_set.name(record.label).map(str::to_string);
+            record
+        }))
+    };
+
+    let mut pairs: Vec<LabelConflictPair> = Vec::new();
+    for (&(a, b), &similarity) in &edges {
+        // a record without a usable label neither agrees nor disagrees
+        let (Some(label_a), Some(label_b)) = (labels.get(&a), labels.get(&b)) else {
+            continue;
+        };
+        if label_a == label_b {
+            continue;
+        }
+        if let (Some(doc_a), Some(doc_b)) = (fetch_record(a)?, fetch_record(b)?) {
+            pairs.push(LabelConflictPair {
+                doc_a,
+                doc_b,
+                similarity,
+            });
+        }
+    }
+    pairs.sort_by(|x, y| {
+        y.similarity
+            .total_cmp(&x.similarity)
+            .then(x.doc_a.id.cmp(&y.doc_a.id))
+            .then(x.doc_b.id.cmp(&y.doc_b.id))
+    });
+
+    let clusters = conflicting_clusters(&edges, &labels);
+
+    Ok(LabelConflictReport {
+        min_similarity,
+        pairs,
+        clusters,
+        label_names: label_set.names().clone(),
+    })
+}
+
+/// Groups the similarity edges into connected components and keeps the mixed-label ones.
+fn conflicting_clusters(
+    edges: &BTreeMap<(u32, u32), f64>,
+    labels: &HashMap<u32, u32>,
+) -> Vec<LabelConflictCluster> {
+    let mut parent: HashMap<u32, u32> = HashMap::new();
+
+    fn find(parent: &mut HashMap<u32, u32>, id: u32) -> u32 {
+        let mut root = id;
+        while let Some(&next) = parent.get(&root) {
+            if next == root {
+                break;
+            }
+            root = next;
+        }
+        parent.insert(id, root);
+        root
+    }
+
+    for &(a, b) in edges.keys() {
+        parent.entry(a).or_insert(a);
+        parent.entry(b).or_insert(b);
+        let (root_a, root_b) = (find(&mut parent, a), find(&mut parent, b));
+        if root_a != root_b {
+            parent.insert(root_a.max(root_b), root_a.min(root_b));
+        }
+    }
+
+    let mut components: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    let ids: Vec<u32> = parent.keys().copied().collect();
+    for id in ids {
+        let root = find(&mut parent, id);
+        components.entry(root).or_default().push(id);
+    }
+
+    let mut clusters: Vec<LabelConflictCluster> = components
+        .into_values()
+        .filter_map(|mut doc_ids| {
+            doc_ids.sort();
+            let mut label_counts: BTreeMap<u32, usize> = BTreeMap::new();
+            for id in &doc_ids {
+                if let Some(label) = labels.get(id) {
+                    *label_counts.entry(*label).or_default() += 1;
+                }
+            }
+            (label_counts.len() > 1).then_some(LabelConflictCluster {
+                doc_ids,
+                label_counts,
+            })
+        })
+        .collect();
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.doc_ids.len()));
+
+    clusters
+}
+
+pub fn run_label_conflicts(args: &Args) {
+    let db_file = args.get_or("db", DEFAULT_DB_FILE);
+    let min_similarity = args.parse_or("min-similarity", DEFAULT_SIMILARITY_THRESHOLD);
+
+    let conn = Connection::open(&db_file).expect("Failed to open database");
+    let report = label_conflicts(&conn, min_similarity).expect("Failed to build report");
+
+    if args.has("json") {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return;
+    }
+
+    println!(
+        "{} conflicting pairs, {} conflicting clusters (similarity >= {})",
+        report.pairs.len(),
+        report.clusters.len(),
+        min_similarity
+    );
+
+    let label = |record: &Record| {
+        record
+            .label_name
+            .clone()
+            .unwrap_or(record.label.to_string())
+    };
+    for pair in &report.pairs {
+        println!(
+            "\n{:.3}  #{} [label {}] {}\n       #{} [label {}] {}",
+            pair.similarity,
+            pair.doc_a.id,
+            label(&pair.doc_a),
+            pair.doc_a.title,
+            pair.doc_b.id,
+            label(&pair.doc_b),
+            pair.doc_b.title
+        );
+    }
+
+    for cluster in &report.clusters {
+        println!(
+            "\ncluster of {} docs {:?}, labels {:?}",
+            cluster.doc_ids.len(),
+            cluster.doc_ids,
+            cluster
+                .label_counts
+                .iter()
+                .map(|(id, count)| {
+                    let name = report.label_names.get(id).cloned();
+                    (name.unwrap_or(id.to_string()), *count)
+                })
+                .collect::<BTreeMap<_, _>>()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_support::{indexed_database, record};
+    use super::*;
+
+    #[test]
+    fn pairs_with_a_missing_label_are_not_conflicts() {
+        let text = "the same few sentences about the weather on the coast this week";
+        let conn = indexed_database(&[
+            record(1, "weather", text, 0),
+            record(2, "weather", text, 1),
+            record(3, "weather", text, 1),
+        ]);
+        conn.execute("UPDATE records SET label = '' WHERE id = 3", ())
+            .unwrap();
+
+        let report = label_conflicts(&conn, 0.5).unwrap();
+        let pairs: Vec<(u32, u32)> = report
+            .pairs
+            .iter()
+            .map(|pair| (pair.doc_a.id, pair.doc_b.id))
+            .collect();
+        assert_eq!(pairs, vec![(1, 2)]);
+    }
+}
@@ -0,0 +1,211 @@
+use rayon::prelude::*;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use super::{PairSimilarity, SimilaritiesDB, SimilarityMetric};
+
+const FINGERPRINT_BITS: u32 = 64;
+
+/// 64 bit SimHash fingerprint of a set of shingles.
+///
+/// Every shingle votes +1 / -1 on each bit of its own hash and the fingerprint keeps the bits
+/// with a positive total, so documents sharing most of their shingles end up a small Hamming
+/// distance apart.
+pub fn simhash(shingles: &HashSet<String>) -> u64 {
+    let mut votes = [0i64; FINGERPRINT_BITS as usize];
+
+    for shingle in shingles {
+        let mut hasher = DefaultHasher::new();
+        shingle.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        for (bit, vote) in votes.iter_mut().enumerate() {
+            if hash >> bit & 1 == 1 {
+                *vote += 1;
+            } else {
+                *vote -= 1;
+            }
+        }
+    }
+
+    votes
+        .iter()
+        .enumerate()
+        .filter(|(_, vote)| **vote > 0)
+        .fold(0u64, |fingerprint, (bit, _)| fingerprint | 1 << bit)
+}
+
+/// Jaccard estimate for two shingle sets of `size_a` and `size_b` whose fingerprints are
+/// `distance` bits apart, so SimHash scores are on the same scale as the MinHash ones.
+///
+/// The share of differing bits estimates the angle between the sets as `π * distance / 64`,
+/// its cosine is |A∩B| / sqrt(|A| |B|), and the intersection it implies gives the Jaccard
+/// similarity |A∩B| / (|A| + |B| - |A∩B|).
+pub fn jaccard_estimate(distance: u32, size_a: usize, size_b: usize) -> f64 {
+    if size_a == 0 || size_b == 0 {
+        return 0.0;
+    }
+    let angle = std::f64::consts::PI * distance as f64 / FINGERPRINT_BITS as f64;
+    let (size_a, size_b) = (size_a as f64, size_b as f64);
+    let intersection = (angle.cos().max(0.0) * (size_a * size_b).sqrt()).min(size_a.min(size_b));
+    intersection / (size_a + size_b - intersection)
+}
+
+/// Candidate search for fingerprints within `max_distance` bits of each other.
+///
+/// The fingerprint is split into `max_distance + 1` blocks. Two fingerprints that differ in at
+/// most `max_distance` bits must agree exactly on at least one block, so bucketing on every
+/// block finds all of them without comparing every pair.
+pub struct SimHashIndex {
+    blocks: Vec<(u32, u64)>,
+    buckets: Vec<HashMap<u64, Vec<u32>>>,
+}
+
+impl SimHashIndex {
+    pub fn new(max_distance: u32) -> SimHashIndex {
+        let block_count = (max_distance + 1).min(FINGERPRINT_BITS);
+        let mut blocks = Vec::new();
+        let mut shift = 0;
+
+        for block in 0..block_count {
+            // spread the remainder over the first blocks
+            let width =
+                FINGERPRINT_BITS / block_count + u32::from(block < FINGERPRINT_BITS % block_count);
+            let mask = if width == 64 {
+                u64::MAX
+            } else {
+                (1 << width) - 1
+            };
+            blocks.push((shift, mask));
+            shift += width;
+        }
+
+        SimHashIndex {
+            buckets: blocks.iter().map(|_| HashMap::new()).collect(),
+            blocks,
+        }
+    }
+
+    /// Largest Hamming distance at which `jaccard_estimate` can still meet a Jaccard threshold.
+    /// Sets of equal size need the smallest angle for a given Jaccard similarity, so their
+    /// distance bounds every other pair's.
+    pub fn max_distance_for(threshold: f64) -> u32 {
+        let threshold = threshold.clamp(0.0, 1.0);
+        let cosine = 2.0 * threshold / (1.0 + threshold);
+        let distance = cosine.acos() / std::f64::consts::PI * FINGERPRINT_BITS as f64;
+        // a distance that lands exactly on a bit count must not round down below it
+        (distance + 1e-9).floor() as u32
+    }
+
+    pub fn insert(&mut self, doc_id: u32, fingerprint: u64) {
+        for (block, (shift, mask)) in self.blocks.iter().enumerate() {
+            self.buckets[block]
+                .entry(fingerprint >> shift & mask)
+                .or_default()
+                .push(doc_id);
+        }
+    }
+
+    pub fn candidates(&self, fingerprint: u64) -> HashSet<u32> {
+        let mut candidates = HashSet::new();
+        for (block, (shift, mask)) in self.blocks.iter().enumerate() {
+            if let Some(bucket) = self.buckets[block].get(&(fingerprint >> shift & mask)) {
+                candidates.extend(bucket);
+            }
+        }
+        candidates
+    }
+}
+
+/// SimHash counterpart of the MinHash similarity stage, producing the same `similarities` rows.
+///
+/// The stored similarity is the `jaccard_estimate` of the fingerprint distance and containment
+/// is counted from the shingle sets. Candidates are bucketed on the similarity threshold, so
+/// with the containment metric only pairs that are also close in Hamming distance are scored.
+pub fn simhash_similarities(
+    shingled_dataset: &HashMap<u32, HashSet<String>>,
+    metric: SimilarityMetric,
+    threshold: f64,
+) -> SimilaritiesDB {
+    let fingerprints: HashMap<u32, u64> = shingled_dataset
+        .par_iter()
+        .map(|(id, shingles)| (*id, simhash(shingles)))
+        .collect();
+
+    let mut index = SimHashIndex::new(SimHashIndex::max_distance_for(threshold));
+    for (id, fingerprint) in &fingerprints {
+        index.insert(*id, *fingerprint);
+    }
+
+    fingerprints
+        .par_iter()
+        .map(|(doc1_id, fingerprint)| {
+            let mut doc_similarities: HashMap<u32, PairSimilarity> = HashMap::new();
+
+            let doc1 = &shingled_dataset[doc1_id];
+            for doc2_id in index.candidates(*fingerprint) {
+                let doc2 = &shingled_dataset[&doc2_id];
+                let distance = (fingerprint ^ fingerprints[&doc2_id]).count_ones();
+                let e = jaccard_estimate(distance, doc1.len(), doc2.len());
+                if metric == SimilarityMetric::Jaccard && e < threshold {
+                    continue;
+                }
+                let pair = PairSimilarity::from_shingles(e, doc1, doc2);
+
+                if pair.score(metric) >= threshold {
+                    doc_similarities.insert(doc2_id, pair);
+                }
+            }
+
+            (*doc1_id, doc_similarities)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shingles(words: &[&str]) -> HashSet<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn identical_sets_have_identical_fingerprints() {
+        let a = shingles(&["the", "quick", "brown", "fox"]);
+        assert_eq!(simhash(&a), simhash(&a.clone()));
+        assert_eq!(jaccard_estimate(0, a.len(), a.len()), 1.0);
+        assert_eq!(jaccard_estimate(0, 0, a.len()), 0.0);
+        // fingerprints past orthogonal share nothing
+        assert_eq!(jaccard_estimate(FINGERPRINT_BITS * 3 / 4, 10, 10), 0.0);
+    }
+
+    #[test]
+    fn max_distance_is_the_last_one_meeting_the_threshold() {
+        let mut previous = FINGERPRINT_BITS;
+        for threshold in [0.0, 0.3, 0.5, 0.7, 0.85, 1.0] {
+            let distance = SimHashIndex::max_distance_for(threshold);
+            assert!(distance <= previous);
+            assert!(jaccard_estimate(distance, 100, 100) >= threshold - 1e-9);
+            if threshold > 0.0 {
+                assert!(jaccard_estimate(distance + 1, 100, 100) < threshold);
+            }
+            previous = distance;
+        }
+    }
+
+    #[test]
+    fn index_finds_fingerprints_within_the_distance() {
+        let mut index = SimHashIndex::new(3);
+        let fingerprint = 0x0123_4567_89ab_cdef_u64;
+        index.insert(1, fingerprint);
+        index.insert(2, !fingerprint);
+
+        // three bits flipped, one in each of three different blocks
+        let near = fingerprint ^ (1 | 1 << 20 | 1 << 40);
+        assert_eq!(index.candidates(near), HashSet::from([1]));
+        assert_eq!(index.candidates(!fingerprint), HashSet::from([2]));
+    }
+}
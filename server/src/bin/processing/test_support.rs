@@ -0,0 +1,126 @@
+//! Records, inputs and scratch files shared by the tests of the pipeline and the server.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rusqlite::{types::Value, Connection};
+use std::path::{Path, PathBuf};
+
+use super::{append::default_settings, ingest::Ingestor, metadata::Metadata, Record};
+
+pub fn record(id: u32, title: &str, text: &str, label: u32) -> Record {
+    Record {
+        id,
+        title: title.to_string(),
+        text: text.to_string(),
+        label,
+        label_name: None,
+        metadata: Metadata::new(),
+    }
+}
+
+/// `count` records of random words from `seed`. Every fourth record is a near copy of the one
+/// before it and the last one an exact copy of the first, so every algorithm finds pairs.
+pub fn corpus(count: u32, seed: u64) -> Vec<Record> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut words = |count: usize| -> Vec<String> {
+        (0..count)
+            .map(|_| format!("word{}", rng.gen_range(0..400)))
+            .collect()
+    };
+
+    let mut records: Vec<Record> = Vec::new();
+    for id in 0..count {
+        let (title, text) = match id {
+            _ if id > 0 && id == count - 1 => (records[0].title.clone(), records[0].text.clone()),
+            _ if id % 4 == 3 => {
+                let mut text: Vec<String> = records[id as usize - 1]
+                    .text
+                    .split(' ')
+                    .map(str::to_string)
+                    .collect();
+                text[id as usize % 5] = words(1).remove(0);
+                (records[id as usize - 1].title.clone(), text.join(" "))
+            }
+            _ => (words(3).join(" "), words(30).join(" ")),
+        };
+        records.push(record(id, &title, &text, id % 2));
+    }
+    records
+}
+
+/// Writes `records` as a `;` separated csv with the default columns.
+pub fn write_input(path: &Path, records: &[Record]) {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_path(path)
+        .unwrap();
+    writer
+        .write_record(["id", "title", "text", "label"])
+        .unwrap();
+    for record in records {
+        writer
+            .write_record([
+                record.id.to_string(),
+                record.title.clone(),
+                record.text.clone(),
+                record.label.to_string(),
+            ])
+            .unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+/// An in-memory database the ingestor built from `records`, as the server finds it.
+pub fn indexed_database(records: &[Record]) -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    {
+        let ingestor = Ingestor::new(&conn, default_settings(&conn, 7).unwrap()).unwrap();
+        ingestor.ingest(records).unwrap();
+        ingestor.materialize(None).unwrap();
+    }
+    conn
+}
+
+/// Every row of `table`, sorted, to compare a database before and after a change.
+pub fn table_rows(conn: &Connection, table: &str) -> Vec<Vec<Value>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table)).unwrap();
+    let columns = stmt.column_count();
+    let mut rows: Vec<Vec<Value>> = stmt
+        .query_map([], |row| (0..columns).map(|i| row.get(i)).collect())
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    rows.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+    rows
+}
+
+/// Makes every later rebuild of `inverse_index` rows fail, the last step of an edit.
+pub fn fail_materialize(conn: &Connection) {
+    conn.execute(
+        "CREATE TRIGGER fail_materialize BEFORE INSERT ON inverse_index
+         BEGIN SELECT RAISE(ABORT, 'materialize failed'); END",
+        (),
+    )
+    .unwrap();
+}
+
+/// An empty directory of its own for one test, removed when dropped.
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    pub fn new(name: &str) -> ScratchDir {
+        let path = std::env::temp_dir().join(format!("processing-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        ScratchDir(path)
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,214 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use super::{minhash_similarity, PairSimilarity, SimilaritiesDB, SimilarityMetric};
+
+/// How tokens are weighted before weighted MinHash sampling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    /// Raw term frequency within the document.
+    Tf,
+    /// Term frequency scaled by `ln(N / df) + 1`, so corpus-wide filler words count less.
+    TfIdf,
+}
+
+impl std::str::FromStr for Weighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tf" => Ok(Weighting::Tf),
+            "tfidf" | "tf-idf" => Ok(Weighting::TfIdf),
+            _ => Err(format!("unknown weighting `{}`", s)),
+        }
+    }
+}
+
+/// Counts every token in `text`, split the same way `tokenize` does.
+pub fn term_frequencies(text: &str) -> HashMap<String, f64> {
+    let mut frequencies: HashMap<String, f64> = HashMap::new();
+    for word in text.split_whitespace() {
+        for token in word.split('.').filter(|token| !token.is_empty()) {
+            *frequencies.entry(token.to_lowercase()).or_default() += 1.0;
+        }
+    }
+    frequencies
+}
+
+pub fn token_weights(
+    documents: &HashMap<u32, String>,
+    weighting: Weighting,
+) -> HashMap<u32, HashMap<String, f64>> {
+    let mut weights: HashMap<u32, HashMap<String, f64>> = documents
+        .par_iter()
+        .map(|(id, text)| (*id, term_frequencies(text)))
+        .collect();
+
+    if weighting == Weighting::TfIdf {
+        let mut document_frequencies: HashMap<String, f64> = HashMap::new();
+        for frequencies in weights.values() {
+            for token in frequencies.keys() {
+                *document_frequencies.entry(token.clone()).or_default() += 1.0;
+            }
+        }
+
+        let total = documents.len() as f64;
+        weights.par_iter_mut().for_each(|(_, frequencies)| {
+            for (token, weight) in frequencies.iter_mut() {
+                *weight *= (total / document_frequencies[token]).ln() + 1.0;
+            }
+        });
+    }
+
+    weights
+}
+
+/// Weighted MinHash signature using Improved Consistent Weighted Sampling (Ioffe, 2010).
+///
+/// For every sample each token draws `r, c ~ Gamma(2, 1)` and `beta ~ U(0, 1)` from a generator
+/// seeded by `(seed, sample, token)`, so the draws are the same for every document. The token
+/// with the smallest `a = c / (y * e^r)` wins, where `y` depends on the token's weight, and the
+/// sample is the pair `(token, t)`. Two signatures agree on a sample with probability equal to
+/// the weighted Jaccard similarity `sum(min) / sum(max)` of the two weight vectors.
+pub fn weighted_minhash_signature(
+    weights: &HashMap<String, f64>,
+    sample_count: usize,
+    seed: u64,
+) -> Vec<u64> {
+    let mut signature: Vec<u64> = Vec::with_capacity(sample_count);
+
+    for sample in 0..sample_count {
+        let mut best: Option<(f64, &String, i64)> = None;
+
+        for (token, weight) in weights.iter().filter(|(_, weight)| **weight > 0.0) {
+            let mut hasher = DefaultHasher::new();
+            (seed, sample, token).hash(&mut hasher);
+            let mut rng = StdRng::seed_from_u64(hasher.finish());
+
+            let mut gamma = || {
+                -(rng.gen::<f64>().max(f64::MIN_POSITIVE).ln())
+                    - rng.gen::<f64>().max(f64::MIN_POSITIVE).ln()
+            };
+            let r = gamma();
+            let c = gamma();
+            let beta: f64 = rng.gen();
+
+            let t = (weight.ln() / r + beta).floor();
+            let y = (r * (t - beta)).exp();
+            let a = c / (y * r.exp());
+
+            if best.is_none_or(|(best_a, _, _)| a < best_a) {
+                best = Some((a, token, t as i64));
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+        if let Some((_, token, t)) = best {
+            (token, t).hash(&mut hasher);
+        }
+        signature.push(hasher.finish());
+    }
+
+    signature
+}
+
+/// Sum of all weights, added in a fixed order so it is the same on every run.
+fn total_weight(weights: &HashMap<String, f64>) -> f64 {
+    let mut values: Vec<f64> = weights.values().copied().collect();
+    values.sort_by(f64::total_cmp);
+    values.iter().sum()
+}
+
+/// Weighted MinHash counterpart of the MinHash similarity stage. Containment is estimated from
+/// the total weight of each document instead of its shingle count.
+pub fn weighted_minhash_similarities(
+    documents: &HashMap<u32, String>,
+    weighting: Weighting,
+    minhash_length: usize,
+    seed: u64,
+    metric: SimilarityMetric,
+    threshold: f64,
+) -> SimilaritiesDB {
+    let weights = token_weights(documents, weighting);
+
+    let signatures: HashMap<u32, (Vec<u64>, f64)> = weights
+        .par_iter()
+        .map(|(id, weights)| {
+            (
+                *id,
+                (
+                    weighted_minhash_signature(weights, minhash_length, seed),
+                    total_weight(weights),
+                ),
+            )
+        })
+        .collect();
+
+    signatures
+        .par_iter()
+        .map(|(doc1_id, (doc1_minhash, doc1_weight))| {
+            let mut doc_similarities: HashMap<u32, PairSimilarity> = HashMap::new();
+
+            for (doc2_id, (doc2_minhash, doc2_weight)) in &signatures {
+                let e = minhash_similarity(doc1_minhash, doc2_minhash);
+                let pair = PairSimilarity::estimate(e, *doc1_weight, *doc2_weight);
+
+                if pair.score(metric) >= threshold {
+                    doc_similarities.insert(*doc2_id, pair);
+                }
+            }
+
+            (*doc1_id, doc_similarities)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weights(pairs: &[(&str, f64)]) -> HashMap<String, f64> {
+        pairs
+            .iter()
+            .map(|(token, weight)| (token.to_string(), *weight))
+            .collect()
+    }
+
+    #[test]
+    fn parses_weightings() {
+        assert_eq!("tf".parse(), Ok(Weighting::Tf));
+        assert_eq!("tfidf".parse(), Ok(Weighting::TfIdf));
+        assert_eq!("tf-idf".parse(), Ok(Weighting::TfIdf));
+        assert!("idf".parse::<Weighting>().is_err());
+    }
+
+    #[test]
+    fn counts_tokens_like_tokenize() {
+        let frequencies = term_frequencies("Storm.coast storm  ..coast");
+        assert_eq!(frequencies, weights(&[("storm", 2.0), ("coast", 2.0)]));
+    }
+
+    #[test]
+    fn signatures_depend_only_on_weights_and_seed() {
+        let a = weights(&[("storm", 2.0), ("coast", 1.0), ("rain", 0.5)]);
+        let signature = weighted_minhash_signature(&a, 32, 7);
+        assert_eq!(signature, weighted_minhash_signature(&a.clone(), 32, 7));
+        assert_ne!(signature, weighted_minhash_signature(&a, 32, 8));
+    }
+
+    #[test]
+    fn agreement_estimates_weighted_jaccard() {
+        // sum(min) / sum(max) = 2 / 4
+        let a = weights(&[("storm", 1.0), ("coast", 1.0), ("rain", 2.0)]);
+        let b = weights(&[("storm", 1.0), ("coast", 1.0)]);
+        let estimate = minhash_similarity(
+            &weighted_minhash_signature(&a, 256, 7),
+            &weighted_minhash_signature(&b, 256, 7),
+        );
+        assert!((estimate - 0.5).abs() < 0.15, "{}", estimate);
+    }
+}
@@ -0,0 +1,400 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::search::{Collapse, Hits};
+
+/// Limits of the search result cache, read from `SEARCH_CACHE_MAX_ENTRIES`,
+/// `SEARCH_CACHE_MAX_MB` and `SEARCH_CACHE_TTL_SECS`.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    pub ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_entries: 1000,
+            max_bytes: 256 * 1024 * 1024,
+            ttl: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn from_env() -> CacheConfig {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok()?.parse().ok()
+        }
+
+        let default = CacheConfig::default();
+        CacheConfig {
+            max_entries: var("SEARCH_CACHE_MAX_ENTRIES")
+                .unwrap_or(default.max_entries)
+                .max(1),
+            max_bytes: var::<usize>("SEARCH_CACHE_MAX_MB")
+                .map_or(default.max_bytes, |mb| mb * 1024 * 1024),
+            ttl: var("SEARCH_CACHE_TTL_SECS").map_or(default.ttl, Duration::from_secs),
+        }
+    }
+}
+
+/// What makes two searches the same: the normalized query words, in any order, plus the
+/// parameters that change the results.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SearchKey {
+    words: Vec<String>,
+    filter: Option<String>,
+    sort: Option<String>,
+    #[serde(default)]
+    boost: Option<String>,
+    #[serde(default)]
+    collapse: Option<Collapse>,
+}
+
+impl SearchKey {
+    pub fn new(
+        words: &[String],
+        filter: Option<&str>,
+        sort: Option<&str>,
+        boost: Option<&str>,
+        collapse: Option<Collapse>,
+    ) -> SearchKey {
+        let mut words = words.to_vec();
+        words.sort();
+        words.dedup();
+        let parameter = |value: Option<&str>| {
+            value
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+
+        SearchKey {
+            words,
+            filter: parameter(filter),
+            sort: parameter(sort),
+            boost: parameter(boost),
+            collapse,
+        }
+    }
+
+    pub fn words(&self) -> &[String] {
+        &self.words
+    }
+
+    pub fn filter(&self) -> Option<&str> {
+        self.filter.as_deref()
+    }
+
+    pub fn sort(&self) -> Option<&str> {
+        self.sort.as_deref()
+    }
+
+    pub fn boost(&self) -> Option<&str> {
+        self.boost.as_deref()
+    }
+
+    pub fn collapse(&self) -> Option<Collapse> {
+        self.collapse
+    }
+}
+
+struct CachedSearch {
+    key: SearchKey,
+    data: Arc<Hits>,
+    created_at: Instant,
+    last_used: u64,
+    size: usize,
+}
+
+/// Result of looking a search up by id.
+pub enum Lookup {
+    Found(SearchKey, Arc<Hits>),
+    /// The search existed but was evicted, outlived its TTL, or the records changed since.
+    Expired,
+    Unknown,
+}
+
+/// Rough size of an entry besides its hits.
+const ENTRY_OVERHEAD: usize = 256;
+
+/// Search ids whose results are gone, kept so `/search-results` can tell them from ids that
+/// never existed.
+const RETIRED_IDS_PER_ENTRY: usize = 10;
+
+/// The ordered hits of recent searches, by id and by `SearchKey`, bounded by entry count and
+/// approximate size, with the least recently used entries evicted first. Pages are read from
+/// the database on demand, only the order of the results is kept.
+pub struct SearchCache {
+    config: CacheConfig,
+    entries: HashMap<Uuid, CachedSearch>,
+    ids: HashMap<SearchKey, Uuid>,
+    recency: BTreeMap<u64, Uuid>,
+    tick: u64,
+    bytes: usize,
+    retired: HashSet<Uuid>,
+    retired_order: VecDeque<Uuid>,
+    pending: HashMap<SearchKey, Arc<tokio::sync::Mutex<()>>>,
+    /// Bumped by `clear`, so a search that read the records before they changed can't cache
+    /// its results after the entries were dropped.
+    generation: u64,
+}
+
+impl SearchCache {
+    pub fn new(config: CacheConfig) -> SearchCache {
+        SearchCache {
+            config,
+            entries: HashMap::new(),
+            ids: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            retired: HashSet::new(),
+            retired_order: VecDeque::new(),
+            pending: HashMap::new(),
+            generation: 0,
+        }
+    }
+
+    fn touch(&mut self, id: Uuid) -> Option<Arc<Hits>> {
+        let entry = self.entries.get_mut(&id)?;
+        if entry.created_at.elapsed() > self.config.ttl {
+            self.remove(id);
+            return None;
+        }
+
+        self.tick += 1;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = self.tick;
+        self.recency.insert(self.tick, id);
+        Some(entry.data.clone())
+    }
+
+    /// The cached hits of a search, with its id.
+    pub fn find(&mut self, key: &SearchKey) -> Option<(Uuid, Arc<Hits>)> {
+        let id = *self.ids.get(key)?;
+        self.touch(id).map(|data| (id, data))
+    }
+
+    pub fn get(&mut self, id: Uuid) -> Lookup {
+        match self.touch(id) {
+            Some(data) => Lookup::Found(self.entries[&id].key.clone(), data),
+            None if self.retired.contains(&id) => Lookup::Expired,
+            None => Lookup::Unknown,
+        }
+    }
+
+    /// Caches the hits of search `id`, replacing what was cached for it or for `key`. Hits
+    /// computed before the cache was last cleared, at an older `generation`, are only returned.
+    pub fn insert(&mut self, id: Uuid, key: SearchKey, hits: Hits, generation: u64) -> Arc<Hits> {
+        if generation != self.generation {
+            return Arc::new(hits);
+        }
+        if let Some(previous) = self.ids.get(&key).copied() {
+            self.remove(previous);
+        }
+        self.remove(id);
+
+        let data = Arc::new(hits);
+        let collapsed = data.collapsed.as_ref().map_or(0, |collapsed| {
+            collapsed.values().map(Vec::len).sum::<usize>() + collapsed.len()
+        });
+        let size = (data.ids.len() + collapsed) * std::mem::size_of::<u32>() + ENTRY_OVERHEAD;
+        self.tick += 1;
+        self.bytes += size;
+        self.ids.insert(key.clone(), id);
+        self.recency.insert(self.tick, id);
+        self.entries.insert(
+            id,
+            CachedSearch {
+                key,
+                data: data.clone(),
+                created_at: Instant::now(),
+                last_used: self.tick,
+                size,
+            },
+        );
+
+        // the new entry stays even if it is over the size limit on its own
+        while self.entries.len() > self.config.max_entries
+            || (self.bytes > self.config.max_bytes && self.entries.len() > 1)
+        {
+            let Some((_, &oldest)) = self.recency.first_key_value() else {
+                break;
+            };
+            self.remove(oldest);
+        }
+
+        data
+    }
+
+    fn remove(&mut self, id: Uuid) {
+        let Some(entry) = self.entries.remove(&id) else {
+            return;
+        };
+        self.ids.remove(&entry.key);
+        self.recency.remove(&entry.last_used);
+        self.bytes -= entry.size;
+
+        self.retired.insert(id);
+        self.retired_order.push_back(id);
+        while self.retired_order.len() > self.config.max_entries * RETIRED_IDS_PER_ENTRY {
+            if let Some(forgotten) = self.retired_order.pop_front() {
+                self.retired.remove(&forgotten);
+            }
+        }
+    }
+
+    /// Drops every entry, for when the records change under them.
+    pub fn clear(&mut self) {
+        let ids: Vec<Uuid> = self.entries.keys().copied().collect();
+        for id in ids {
+            self.remove(id);
+        }
+        self.generation += 1;
+    }
+
+    /// Read before a search looks at the records and passed to `insert` with its hits.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Forgets everything, including which ids expired, for a cache left half-updated by a
+    /// request that panicked while holding it.
+    pub fn reset(&mut self) {
+        let generation = self.generation + 1;
+        *self = SearchCache::new(self.config.clone());
+        self.generation = generation;
+    }
+
+    /// Lock held while a search is computed. Identical searches arriving in the meantime wait
+    /// on it and then find the result in the cache instead of running the query again.
+    pub fn pending(&mut self, key: &SearchKey) -> Arc<tokio::sync::Mutex<()>> {
+        self.pending.entry(key.clone()).or_default().clone()
+    }
+
+    /// Drops the lock `pending` handed out for `key`, unless a newer search replaced it.
+    pub fn finish_pending(&mut self, key: &SearchKey, lock: &Arc<tokio::sync::Mutex<()>>) {
+        if self
+            .pending
+            .get(key)
+            .is_some_and(|pending| Arc::ptr_eq(pending, lock))
+        {
+            self.pending.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(words: &[&str]) -> SearchKey {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        SearchKey::new(&words, None, None, None, None)
+    }
+
+    fn cache(max_entries: usize) -> SearchCache {
+        SearchCache::new(CacheConfig {
+            max_entries,
+            ..CacheConfig::default()
+        })
+    }
+
+    #[test]
+    fn keys_ignore_word_order_and_blank_parameters() {
+        let words = [
+            "storm".to_string(),
+            "coast".to_string(),
+            "storm".to_string(),
+        ];
+        assert_eq!(
+            SearchKey::new(&words, Some(" label=1 "), Some(""), None, None),
+            SearchKey::new(
+                &["coast".to_string(), "storm".to_string()],
+                Some("label=1"),
+                None,
+                None,
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn finds_cached_searches_by_key_and_id() {
+        let mut cache = cache(10);
+        let id = Uuid::new_v4();
+        cache.insert(id, key(&["storm"]), Hits::new(vec![3, 1, 2]), 0);
+
+        let (found, hits) = cache.find(&key(&["storm"])).unwrap();
+        assert_eq!((found, hits.ids.clone()), (id, vec![3, 1, 2]));
+        assert!(matches!(cache.get(id), Lookup::Found(found, _) if found == key(&["storm"])));
+        assert!(cache.find(&key(&["coast"])).is_none());
+        assert!(matches!(cache.get(Uuid::new_v4()), Lookup::Unknown));
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_search() {
+        let mut cache = cache(2);
+        let (storm, coast, rain) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        cache.insert(storm, key(&["storm"]), Hits::new(vec![1]), 0);
+        cache.insert(coast, key(&["coast"]), Hits::new(vec![2]), 0);
+        cache.find(&key(&["storm"]));
+        cache.insert(rain, key(&["rain"]), Hits::new(vec![3]), 0);
+
+        assert!(matches!(cache.get(coast), Lookup::Expired));
+        assert!(matches!(cache.get(storm), Lookup::Found(..)));
+        assert!(matches!(cache.get(rain), Lookup::Found(..)));
+    }
+
+    #[test]
+    fn clear_expires_searches_and_rejects_older_hits() {
+        let mut cache = cache(10);
+        let id = Uuid::new_v4();
+        let generation = cache.generation();
+        cache.insert(id, key(&["storm"]), Hits::new(vec![1]), generation);
+        cache.clear();
+        assert!(matches!(cache.get(id), Lookup::Expired));
+
+        // computed from the records as they were before the clear
+        let late = Uuid::new_v4();
+        let hits = cache.insert(late, key(&["storm"]), Hits::new(vec![1]), generation);
+        assert_eq!(hits.ids, vec![1]);
+        assert!(cache.find(&key(&["storm"])).is_none());
+        assert!(matches!(cache.get(late), Lookup::Unknown));
+
+        cache.insert(
+            late,
+            key(&["storm"]),
+            Hits::new(vec![1]),
+            cache.generation(),
+        );
+        assert!(cache.find(&key(&["storm"])).is_some());
+    }
+
+    #[test]
+    fn identical_searches_share_a_pending_lock() {
+        let mut cache = cache(10);
+        let first = cache.pending(&key(&["storm"]));
+        assert!(Arc::ptr_eq(&first, &cache.pending(&key(&["storm"]))));
+        let other = cache.pending(&key(&["coast"]));
+        assert!(!Arc::ptr_eq(&first, &other));
+        cache.finish_pending(&key(&["coast"]), &other);
+
+        cache.finish_pending(&key(&["storm"]), &first);
+        let second = cache.pending(&key(&["storm"]));
+        assert!(!Arc::ptr_eq(&first, &second));
+        // a search that waited on the first lock leaves the second in place
+        cache.finish_pending(&key(&["storm"]), &first);
+        assert!(Arc::ptr_eq(&second, &cache.pending(&key(&["storm"]))));
+        cache.finish_pending(&key(&["storm"]), &second);
+        assert!(cache.pending.is_empty());
+    }
+}
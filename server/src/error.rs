@@ -0,0 +1,200 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use crate::{
+    bin::processing::{append::AppendError, edit::EditError},
+    search::SearchError,
+};
+
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+/// Everything a request can fail with. Each kind has its own status code, and every error is
+/// returned as `{"error": <kind>, "message": <message>}`.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    BadRequest(String),
+    /// A search whose results are gone and can't be run again.
+    Expired(String),
+    /// The database can't serve the request yet.
+    Unavailable(String),
+    /// A request that ran past the server's time limit.
+    Timeout(String),
+    /// The database failed. The message says what was being done, the cause is only logged.
+    Storage {
+        message: String,
+        source: Source,
+    },
+    Internal(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Expired(_) => StatusCode::GONE,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Timeout(_) => StatusCode::REQUEST_TIMEOUT,
+            AppError::Storage { .. } | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Expired(_) => "expired",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::Timeout(_) => "timeout",
+            AppError::Storage { .. } => "storage",
+            AppError::Internal(_) => "internal",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            AppError::NotFound(message)
+            | AppError::BadRequest(message)
+            | AppError::Expired(message)
+            | AppError::Unavailable(message)
+            | AppError::Timeout(message)
+            | AppError::Storage { message, .. }
+            | AppError::Internal(message) => message,
+        }
+    }
+
+    fn storage(source: impl Into<Source>) -> AppError {
+        AppError::Storage {
+            message: "Database error".to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// Maps the error of a `db.call`. Errors the closure raised on purpose keep their kind,
+    /// anything else is a storage error described by `message`.
+    pub fn db(message: &str) -> impl FnOnce(tokio_rusqlite::Error) -> AppError + '_ {
+        move |e| {
+            let e = match e {
+                tokio_rusqlite::Error::Other(other) => from_source(other),
+                e => AppError::storage(e),
+            };
+            match e {
+                AppError::Storage { source, .. } => AppError::Storage {
+                    message: message.to_string(),
+                    source,
+                },
+                e => e,
+            }
+        }
+    }
+}
+
+/// The `AppError` behind an error boxed by `tokio_rusqlite`.
+fn from_source(source: Source) -> AppError {
+    let source = match source.downcast::<AppError>() {
+        Ok(e) => return *e,
+        Err(source) => source,
+    };
+    let source = match source.downcast::<SearchError>() {
+        Ok(e) => return (*e).into(),
+        Err(source) => source,
+    };
+    let source = match source.downcast::<AppendError>() {
+        Ok(e) => return (*e).into(),
+        Err(source) => source,
+    };
+    match source.downcast::<EditError>() {
+        Ok(e) => (*e).into(),
+        Err(source) => AppError::storage(source),
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Storage { message, source } => write!(f, "{}: {}", message, source),
+            e => f.write_str(e.message()),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if self.status().is_server_error() {
+            tracing::error!("{}", self);
+        }
+        let body = json!({"error": self.kind(), "message": self.message()});
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+/// Lets `db.call` closures fail with an `AppError` through `?`.
+impl From<AppError> for tokio_rusqlite::Error {
+    fn from(e: AppError) -> Self {
+        tokio_rusqlite::Error::Other(Box::new(e))
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        AppError::storage(e)
+    }
+}
+
+impl From<SearchError> for AppError {
+    fn from(e: SearchError) -> Self {
+        match e {
+            SearchError::Invalid(reason) => AppError::BadRequest(reason),
+            SearchError::Database(e) => AppError::storage(e),
+        }
+    }
+}
+
+impl From<AppendError> for AppError {
+    fn from(e: AppendError) -> Self {
+        match e {
+            AppendError::Invalid(reason) => AppError::BadRequest(reason),
+            not_indexed @ AppendError::NotIndexed => AppError::Unavailable(not_indexed.to_string()),
+            AppendError::Database(e) => AppError::storage(e),
+        }
+    }
+}
+
+impl From<EditError> for AppError {
+    fn from(e: EditError) -> Self {
+        match e {
+            EditError::Invalid(reason) => AppError::BadRequest(reason),
+            not_found @ EditError::NotFound(_) => AppError::NotFound(not_found.to_string()),
+            not_indexed @ EditError::NotIndexed => AppError::Unavailable(not_indexed.to_string()),
+            EditError::Database(e) => AppError::storage(e),
+        }
+    }
+}
+
+// malformed query strings, paths and bodies get the same json body as every other error
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::BadRequest(rejection.body_text())
+    }
+}
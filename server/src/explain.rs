@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::{
+    bin::processing::{
+        fields::{self, Field},
+        normalize::Normalization,
+        Record,
+    },
+    search::{Boosts, Term},
+};
+
+/// Why a hit matched, with `explain=true`: every query term it matched, in which field, and
+/// what that added to its relevance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Explanation {
+    pub score: f64,
+    pub matches: Vec<TermMatch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TermMatch {
+    /// the query word, with its `field:` prefix if it had one
+    pub term: String,
+    /// none for databases without a field index, which don't tell fields apart
+    pub field: Option<String>,
+    pub score: f64,
+    /// indexed tokens of the field that contain the term
+    pub tokens: Vec<String>,
+}
+
+/// Explains the relevance `search::scored_matches` gave `record`, by matching the terms against
+/// the tokens the index holds for it.
+pub fn explain(
+    record: &Record,
+    terms: &[Term],
+    boosts: &Boosts,
+    normalization: &Normalization,
+    field_index: bool,
+) -> Explanation {
+    let field_tokens = fields::field_tokens(&normalization.record(record));
+    let mut matches = Vec::new();
+
+    for term in terms {
+        let matching = |field: Field| -> Vec<String> {
+            field_tokens
+                .iter()
+                .filter(|(own, _)| *own == field)
+                .flat_map(|(_, tokens)| tokens)
+                .filter(|token| token.contains(&term.word))
+                .cloned()
+                .collect()
+        };
+        let name = match term.field {
+            Some(field) => format!("{}:{}", field, term.word),
+            None => term.word.clone(),
+        };
+
+        if !field_index {
+            // the combined index counts a term once, as a text match
+            let mut tokens: Vec<String> = Field::ALL.into_iter().flat_map(matching).collect();
+            tokens.sort();
+            tokens.dedup();
+            if !tokens.is_empty() {
+                matches.push(TermMatch {
+                    term: name,
+                    field: None,
+                    score: boosts.text,
+                    tokens,
+                });
+            }
+            continue;
+        }
+
+        for field in Field::ALL {
+            let tokens = matching(field);
+            if term.searches(field) && !tokens.is_empty() {
+                matches.push(TermMatch {
+                    term: name.clone(),
+                    field: Some(field.to_string()),
+                    score: boosts.get(field),
+                    tokens,
+                });
+            }
+        }
+    }
+
+    Explanation {
+        score: matches
+            .iter()
+            .fold(0.0, |score, term_match| score + term_match.score),
+        matches,
+    }
+}
+
+/// Milliseconds spent in each stage of a request, with `explain=true`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Timings {
+    /// reading the query, the database settings and the cursor
+    pub parse_ms: f64,
+    /// finding the records that match the terms
+    pub index_lookup_ms: f64,
+    /// relevance order, filters, sort and collapsing
+    pub ordering_ms: f64,
+    /// reading the records of the page
+    pub record_fetch_ms: f64,
+    /// reading the similar documents of the page
+    pub similarity_fetch_ms: f64,
+    pub total_ms: f64,
+    /// the hits came from the cache, without an index lookup or ordering
+    pub cached: bool,
+}
+
+/// Milliseconds, to the microsecond.
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Milliseconds since `start`.
+pub fn since(start: Instant) -> f64 {
+    millis(start.elapsed())
+}
@@ -1,249 +1,557 @@
 use axum::{
-    extract::{Query, State},
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
     http::StatusCode,
     response::IntoResponse,
     Json,
 };
-use rusqlite::params;
-use serde_json::json;
-use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-};
+use std::{collections::HashMap, sync::Arc, time::Instant};
 use uuid::Uuid;
 
 use crate::{
-    bin::processing::Record,
-    model::{RecordResponse, SearchResultsRes, SimilarInfo, SimilarityInfoFull},
-    schema::{SearchReq, SearchResultsReq},
-    AppState, QueryState,
+    bin::processing::{
+        append, edit, fields,
+        labels::LabelSet,
+        metadata::{self, MetadataType, SortKey},
+        normalize::Normalization,
+        reports, DEFAULT_SIMILARITY_THRESHOLD,
+    },
+    cache::{Lookup, SearchKey},
+    error::AppError,
+    explain::{self, Timings},
+    model::{CollapsedDuplicates, RecentSearchesRes, SearchResultsRes},
+    schema::{
+        AddRecordsReq, LabelConflictsReq, RecentSearchesReq, SearchReq, SearchResultsReq,
+        UpdateRecordReq,
+    },
+    search::{self, Browse, Collapse, Cursor, Hits, Term},
+    searches,
+    snippet::{self, Highlight},
+    AppState,
 };
 
-const QUERY_LIMIT: u32 = 20;
-const SIMILARITY_DOC_LIMIT: u32 = 5;
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
 
 pub async fn search_handler(
-    query: Query<SearchReq>,
+    query: Result<Query<SearchReq>, QueryRejection>,
     State(data): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+) -> Result<impl IntoResponse, AppError> {
+    let Query(query) = query?;
+    let started = Instant::now();
+    let mut timings = Timings::default();
     let search_words = query.search_text.to_owned().unwrap_or("".to_string());
 
-    for (key, value) in data.cached_queries.lock().unwrap().iter() {
-        if value.text_query == search_words {
-            tracing::info!(
-                "Returned Cashed Query: {}, with id: {}",
-                value.text_query,
-                key
-            );
-            return Ok(Json(SearchResultsRes {
-                search_id: *key,
-                data: value.data.clone(),
-                number_of_results: value.data.len() as u32,
-                page: 1,
-                total_pages: value.data.len().div_ceil(QUERY_LIMIT as usize) as u32,
-            }));
+    let (metadata_fields, normalization) = data
+        .db
+        .call(|conn| Ok((metadata::fields(conn)?, Normalization::load(conn)?)))
+        .await
+        .map_err(AppError::db("Failed to read database settings"))?;
+    let search_words = search::query_words(&search_words, &normalization);
+
+    let search_key = SearchKey::new(
+        &search_words,
+        query.filter.as_deref(),
+        query.sort.as_deref(),
+        query.boost.as_deref(),
+        query.collapse,
+    );
+    let (page, page_size) = match &query.cursor {
+        Some(cursor) => {
+            let cursor = Cursor::decode(cursor, &search_key).map_err(AppError::BadRequest)?;
+            (cursor.page, cursor.page_size)
+        }
+        None => (
+            query.page.unwrap_or(1),
+            query.page_size.unwrap_or(DEFAULT_PAGE_SIZE),
+        ),
+    };
+    timings.parse_ms = explain::since(started);
+
+    let (search_id, hits) = match cached_hits(&data, &search_key) {
+        Some(cached) => {
+            timings.cached = true;
+            cached
         }
+        None => {
+            run_search(
+                &data,
+                &search_key,
+                query.search_text.clone().unwrap_or_default(),
+                metadata_fields.clone(),
+                &mut timings,
+            )
+            .await?
+        }
+    };
+
+    let request = PageRequest {
+        page,
+        page_size,
+        include_similar: query.include_similar.unwrap_or(true),
+        include_text: query.include_text.unwrap_or(true),
+        highlight: query.highlight.unwrap_or_default(),
+        explain: query.explain.unwrap_or(false),
+    };
+    let mut page = results_page(
+        &data,
+        search_id,
+        &search_key,
+        hits,
+        request,
+        metadata_fields,
+        timings,
+    )
+    .await?;
+    if let Some(timings) = &mut page.timings {
+        timings.total_ms = explain::since(started);
     }
+    Ok(Json(page))
+}
 
-    let search_words = search_words
-        .split_whitespace()
-        .map(|v| v.to_string())
-        .collect::<Vec<String>>();
+/// Finds and orders the hits of a search that isn't cached, records the run in the `searches`
+/// table and caches the hits. The time it takes goes to `timings`.
+async fn run_search(
+    data: &AppState,
+    search_key: &SearchKey,
+    search_text: String,
+    metadata_fields: HashMap<String, MetadataType>,
+    timings: &mut Timings,
+) -> Result<(Uuid, Arc<Hits>), AppError> {
+    let label_set = data
+        .db
+        .call(|conn| Ok(LabelSet::load(conn)?))
+        .await
+        .map_err(AppError::db("Failed to read labels"))?;
+    let filters = metadata::parse_filters(
+        search_key.filter().unwrap_or(""),
+        &metadata_fields,
+        &label_set,
+    )
+    .map_err(AppError::BadRequest)?;
+    let sort_key = search_key
+        .sort()
+        .map(|sort| SortKey::parse(sort, &metadata_fields))
+        .transpose()
+        .map_err(AppError::BadRequest)?;
+    let boosts = data
+        .boosts
+        .with(search_key.boost().unwrap_or(""))
+        .map_err(AppError::BadRequest)?;
+    let terms: Vec<Term> = search_key
+        .words()
+        .iter()
+        .map(|word| Term::parse(word))
+        .collect();
 
-    let search_entries = data
+    // identical searches running at the same time are computed once
+    let pending = PendingSearch::new(data, search_key);
+    let _computing = pending.lock.lock().await;
+    if let Some(cached) = cached_hits(data, search_key) {
+        timings.cached = true;
+        return Ok(cached);
+    }
+
+    let generation = data.search_cache().generation();
+    let key = search_key.clone();
+    let hits = data
         .db
-        .call(|conn| {
-            let mut stmt_inv_idx =
-                conn.prepare("SELECT entries FROM inverse_index WHERE string LIKE (?1)")?;
-            let mut inv_idx_result_set = HashSet::<String>::new();
-
-            for word in search_words {
-                let rows = stmt_inv_idx
-                    .query_map(params![format!("%{}%", word)], |row| {
-                        Ok(row
-                            .get::<_, String>(0)
-                            .unwrap()
-                            .strip_prefix("[")
-                            .unwrap()
-                            .strip_suffix("]")
-                            .unwrap()
-                            .split(", ")
-                            // .map(|e| e.parse::<u32>().unwrap())
-                            .map(|e| e.to_string())
-                            .collect::<Vec<String>>())
-                    })
-                    .unwrap()
-                    .flatten()
-                    .flatten()
-                    .collect::<Vec<_>>();
-
-                for r in rows {
-                    inv_idx_result_set.insert(r);
+        .call(move |conn| {
+            let started = Instant::now();
+            // a search without words browses the whole corpus, paged by SQLite when it can be
+            let browse = match terms.is_empty() {
+                true => Browse::new(conn, &filters, sort_key.as_ref())?,
+                false => None,
+            };
+            let scores = match (&browse, terms.is_empty()) {
+                (Some(_), _) => HashMap::new(),
+                (None, true) => search::all_records(conn)?,
+                (None, false) => {
+                    search::scored_matches(conn, &terms, &boosts).map_err(AppError::from)?
                 }
+            };
+            let found = match &browse {
+                Some(_) => search::has_records(conn)?,
+                None => !scores.is_empty(),
+            };
+            if !found {
+                return Err(AppError::NotFound("No matching records found".to_string()).into());
             }
+            let index_lookup = started.elapsed();
 
-            if inv_idx_result_set.len() == 0 {
-                return Err(tokio_rusqlite::Error::Other(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "404",
-                ))));
-            }
-
-            let mut stmt_records = conn.prepare("SELECT * FROM records WHERE id = (?1)")?;
-            let mut records_result_set: HashMap<u32, Record> = HashMap::new();
-            let mut extended_records_result_set: Vec<RecordResponse> = Vec::new();
-
-            let mut stmt_similar_docs = conn
-                .prepare("SELECT similar_documents FROM similarities WHERE document_id = (?1)")?;
-
-            for id in &inv_idx_result_set {
-                let record_rows = stmt_records
-                    .query_map(params![id], |row| Ok(Record::from(row)))
-                    .unwrap()
-                    .flatten()
-                    .collect::<Vec<Record>>();
-
-                records_result_set.insert(record_rows[0].id, record_rows[0].clone());
-
-                let similarity_rows = stmt_similar_docs
-                    .query_map(params![id], |row| {
-                        Ok(row
-                            .get::<_, String>(0)
-                            .unwrap()
-                            .strip_prefix("[{")
-                            .unwrap()
-                            .strip_suffix("},]")
-                            .unwrap()
-                            .split("},{")
-                            .map(|e: &str| {
-                                // println!("{{{}}}", e);
-                                serde_json::from_str::<SimilarInfo>(&format!("{{{}}}", e)).unwrap()
-                            })
-                            .collect::<Vec<SimilarInfo>>())
-                    })
-                    .unwrap()
-                    .flatten()
-                    .flatten()
-                    .collect::<Vec<_>>();
-
-                let mut similarity_info: Vec<SimilarityInfoFull> = Vec::new();
-
-                let mut count = 0;
-                for r in similarity_rows {
-                    if records_result_set.contains_key(&(r.doc_id as u32)) {
-                        similarity_info.push(SimilarityInfoFull {
-                            doc: records_result_set.get(&(r.doc_id as u32)).unwrap().clone(),
-                            similarity: r.similarity,
-                        });
-                    } else {
-                        let new_record = stmt_records
-                            .query_map(params![r.doc_id], |row| Ok(Record::from(row)))
-                            .unwrap()
-                            .flatten()
-                            .collect::<Vec<Record>>()
-                            .pop()
-                            .unwrap();
-
-                        similarity_info.push(SimilarityInfoFull {
-                            doc: new_record,
-                            similarity: r.similarity,
-                        });
-                    }
-                    count += 1;
-                    if count >= SIMILARITY_DOC_LIMIT {
-                        break;
+            let started = Instant::now();
+            let hits = match browse {
+                Some(browse) => browse.into_hits(conn, key.collapse())?,
+                None => {
+                    let ids = search::ordered_hits(
+                        conn,
+                        scores,
+                        &filters,
+                        sort_key.as_ref(),
+                        &metadata_fields,
+                    )?;
+                    match key.collapse() {
+                        Some(Collapse::Duplicates) => search::collapse_duplicates(conn, ids)?,
+                        None => Hits::new(ids),
                     }
                 }
-                extended_records_result_set.push(RecordResponse {
-                    data: record_rows[0].clone(),
-                    similar_docs: similarity_info,
-                });
-            }
-
-            Ok(extended_records_result_set)
+            };
+            let ordering = started.elapsed();
+            let search_id = searches::record_run(conn, &key, &search_text, hits.count())?;
+            Ok((search_id, hits, index_lookup, ordering))
         })
         .await;
 
-    let search_entries = match search_entries {
-        Ok(entries) => entries,
-        Err(e) => {
-            let error_message = if let tokio_rusqlite::Error::Other(err) = &e {
-                if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
-                    if io_err.kind() == std::io::ErrorKind::NotFound {
-                        "No matching records found"
-                    } else {
-                        "Failed to retrieve search entries"
-                    }
-                } else {
-                    "Failed to retrieve search entries"
-                }
-            } else {
-                "Failed to retrieve search entries"
+    let (search_id, hits, index_lookup, ordering) = match hits {
+        Ok(hits) => hits,
+        Err(e) => return Err(AppError::db("Failed to retrieve search entries")(e)),
+    };
+    timings.index_lookup_ms = explain::millis(index_lookup);
+    timings.ordering_ms = explain::millis(ordering);
+
+    // cached before the pending lock goes, so searches arriving after it find the hits
+    let hits = data
+        .search_cache()
+        .insert(search_id, search_key.clone(), hits, generation);
+    tracing::info!("Cashed Query: {:?}, with id: {}", search_key, &search_id);
+    Ok((search_id, hits))
+}
+
+/// The pending lock of a search, taken out of the cache however `run_search` returns.
+struct PendingSearch<'a> {
+    data: &'a AppState,
+    key: &'a SearchKey,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> PendingSearch<'a> {
+    fn new(data: &'a AppState, key: &'a SearchKey) -> PendingSearch<'a> {
+        PendingSearch {
+            data,
+            key,
+            lock: data.search_cache().pending(key),
+        }
+    }
+}
+
+impl Drop for PendingSearch<'_> {
+    fn drop(&mut self) {
+        self.data
+            .search_cache()
+            .finish_pending(self.key, &self.lock);
+    }
+}
+
+fn cached_hits(data: &AppState, key: &SearchKey) -> Option<(Uuid, Arc<Hits>)> {
+    let (search_id, hits) = data.search_cache().find(key)?;
+    tracing::info!("Returned Cashed Query: {:?}, with id: {}", key, search_id);
+    Some((search_id, hits))
+}
+
+/// Which part of a search's results to return.
+struct PageRequest {
+    page: u32,
+    page_size: u32,
+    /// whether each hit comes with its most similar documents
+    include_similar: bool,
+    /// whether records keep their full text, the snippets are always there
+    include_text: bool,
+    highlight: Highlight,
+    /// whether hits explain their relevance and the response reports its timings
+    explain: bool,
+}
+
+/// Reads one page of `hits` from the database. `timings` holds what the request spent so far.
+async fn results_page(
+    data: &AppState,
+    search_id: Uuid,
+    search_key: &SearchKey,
+    hits: Arc<Hits>,
+    request: PageRequest,
+    metadata_fields: HashMap<String, MetadataType>,
+    mut timings: Timings,
+) -> Result<SearchResultsRes, AppError> {
+    let PageRequest {
+        page,
+        page_size,
+        include_similar,
+        include_text,
+        highlight,
+        explain,
+    } = request;
+    if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(AppError::BadRequest(format!(
+            "page_size must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let total_pages = hits.count().div_ceil(page_size as usize) as u32;
+    // a search whose results were all filtered out still has an empty first page
+    if page == 0 || page > total_pages.max(1) {
+        return Err(AppError::BadRequest(
+            "Invalid page number provided".to_string(),
+        ));
+    }
+
+    let start = ((page - 1) * page_size) as usize;
+    let end = (start + page_size as usize).min(hits.count());
+    let page_hits = hits.clone();
+    let (mut entries, page_timings, explain_settings) = data
+        .db
+        .call(move |conn| {
+            let mut timings = Timings::default();
+            let page_ids = page_hits.page(conn, start..end)?;
+            let entries = search::load_page(
+                conn,
+                &page_ids,
+                &metadata_fields,
+                include_similar,
+                &mut timings,
+            )?;
+            // explanations tokenize the hits the way the index did
+            let explain_settings = match explain {
+                true => Some((
+                    Normalization::load(conn)?,
+                    fields::table_exists(conn, "field_index")?,
+                )),
+                false => None,
             };
+            Ok((entries, timings, explain_settings))
+        })
+        .await
+        .map_err(AppError::db("Failed to retrieve search entries"))?;
+
+    timings.record_fetch_ms = page_timings.record_fetch_ms;
+    timings.similarity_fetch_ms = page_timings.similarity_fetch_ms;
 
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"message": error_message})),
+    let terms: Vec<Term> = search_key
+        .words()
+        .iter()
+        .map(|word| Term::parse(word))
+        .collect();
+    let boosts = data
+        .boosts
+        .with(search_key.boost().unwrap_or(""))
+        .unwrap_or(data.boosts);
+    for entry in &mut entries {
+        if let Some((normalization, field_index)) = &explain_settings {
+            entry.explanation = Some(explain::explain(
+                &entry.data,
+                &terms,
+                &boosts,
+                normalization,
+                *field_index,
             ));
         }
+        if let Some(collapsed) = &hits.collapsed {
+            let ids = collapsed.get(&entry.data.id).cloned().unwrap_or_default();
+            entry.duplicates = Some(CollapsedDuplicates {
+                count: ids.len() as u32,
+                ids,
+            });
+        }
+        entry.snippet = Some(snippet::snippet(&entry.data, search_key.words(), highlight));
+        if !include_text {
+            entry.data.text.clear();
+            for similar in &mut entry.similar_docs {
+                similar.doc.text.clear();
+            }
+        }
+    }
+
+    Ok(SearchResultsRes {
+        search_id,
+        data: entries,
+        number_of_results: hits.count() as u32,
+        page,
+        page_size,
+        total_pages,
+        next_cursor: (page < total_pages)
+            .then(|| Cursor::new(search_key, page + 1, page_size).encode()),
+        timings: explain.then_some(timings),
+    })
+}
+
+pub async fn search_pagination_handler(
+    query: Result<Query<SearchResultsReq>, QueryRejection>,
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let Query(query) = query?;
+    let started = Instant::now();
+    let mut timings = Timings::default();
+    let query_id = query.query_id;
+    let (metadata_fields, saved) = data
+        .db
+        .call(move |conn| Ok((metadata::fields(conn)?, searches::load(conn, query_id)?)))
+        .await
+        .map_err(AppError::db("Failed to read database settings"))?;
+
+    timings.parse_ms = explain::since(started);
+
+    let lookup = data.search_cache().get(query_id);
+    let (search_key, hits) = match (lookup, saved) {
+        (Lookup::Found(search_key, hits), _) => {
+            timings.cached = true;
+            (search_key, hits)
+        }
+        // searches that are no longer cached run again from their stored parameters
+        (_, Some(saved)) => {
+            let (_, hits) = run_search(
+                &data,
+                &saved.key,
+                saved.search_text,
+                metadata_fields.clone(),
+                &mut timings,
+            )
+            .await?;
+            (saved.key, hits)
+        }
+        (Lookup::Expired, None) => {
+            return Err(AppError::Expired(
+                "Search expired, run the query again".to_string(),
+            ))
+        }
+        (Lookup::Unknown, None) => {
+            return Err(AppError::BadRequest(
+                "Invalid query id provided".to_string(),
+            ))
+        }
     };
 
-    let new_search_id = Uuid::new_v4();
-    data.cached_queries.lock().unwrap().insert(
-        new_search_id,
-        QueryState {
-            text_query: query.search_text.clone().unwrap(),
-            data: search_entries.clone(),
-        },
-    );
-    tracing::info!(
-        "Cashed Query: {}, with id: {}",
-        query.search_text.clone().unwrap(),
-        &new_search_id,
-    );
+    let request = PageRequest {
+        page: query.page,
+        page_size: query.page_size.unwrap_or(DEFAULT_PAGE_SIZE),
+        include_similar: query.include_similar.unwrap_or(true),
+        include_text: query.include_text.unwrap_or(true),
+        highlight: query.highlight.unwrap_or_default(),
+        explain: query.explain.unwrap_or(false),
+    };
+    let mut page = results_page(
+        &data,
+        query.query_id,
+        &search_key,
+        hits,
+        request,
+        metadata_fields,
+        timings,
+    )
+    .await?;
+    if let Some(timings) = &mut page.timings {
+        timings.total_ms = explain::since(started);
+    }
+    Ok(Json(page))
+}
+
+pub async fn recent_searches_handler(
+    query: Result<Query<RecentSearchesReq>, QueryRejection>,
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
 
-    let end = search_entries.len().min(QUERY_LIMIT as usize);
+    let recent = data
+        .db
+        .call(move |conn| Ok(searches::recent(conn, limit)?))
+        .await
+        .map_err(AppError::db("Failed to read recent searches"))?;
 
-    Ok(Json(SearchResultsRes {
-        search_id: new_search_id,
-        data: search_entries.get(0..end).unwrap().to_vec(),
-        number_of_results: search_entries.len() as u32,
-        page: 1,
-        total_pages: search_entries.len().div_ceil(QUERY_LIMIT as usize) as u32,
-    }))
+    Ok(Json(RecentSearchesRes { searches: recent }))
 }
 
-pub async fn search_pagination_handler(
-    query: Query<SearchResultsReq>,
+pub async fn label_conflicts_handler(
+    query: Result<Query<LabelConflictsReq>, QueryRejection>,
     State(data): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let cached_queries = &data.cached_queries.lock().unwrap();
+) -> Result<impl IntoResponse, AppError> {
+    let Query(query) = query?;
+    let min_similarity = query.min_similarity.unwrap_or(DEFAULT_SIMILARITY_THRESHOLD);
 
-    if !cached_queries.contains_key(&query.query_id) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(json!({"message": "Invalid query id provided or query expired"})),
+    if !(0.0..=1.0).contains(&min_similarity) {
+        return Err(AppError::BadRequest(
+            "min_similarity must be between 0 and 1".to_string(),
         ));
     }
 
-    let data = &cached_queries.get(&query.query_id).unwrap().data;
-    let total_pages = data.len().div_ceil(QUERY_LIMIT as usize) as u32;
+    let report = data
+        .db
+        .call(move |conn| Ok(reports::label_conflicts(conn, min_similarity)?))
+        .await
+        .map_err(AppError::db("Failed to build label conflict report"))?;
 
-    if query.page <= 0 || query.page > total_pages {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(json!({"message": "Invalid page number provided"})),
-        ));
+    Ok(Json(report))
+}
+
+pub async fn add_records_handler(
+    State(data): State<Arc<AppState>>,
+    body: Result<Json<AddRecordsReq>, JsonRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let Json(body) = body?;
+    if body.records.is_empty() {
+        return Err(AppError::BadRequest("No records provided".to_string()));
     }
 
-    let start = ((query.page - 1) * QUERY_LIMIT) as usize;
-    let end = (((query.page - 1) * QUERY_LIMIT + QUERY_LIMIT) as usize).min(data.len());
+    let report = data
+        .db
+        .call(move |conn| Ok(append::append_records(conn, body.records).map_err(AppError::from)?))
+        .await
+        .map_err(AppError::db("Failed to add records"))?;
 
-    Ok(Json(SearchResultsRes {
-        search_id: query.query_id,
-        data: data.get(start..end).unwrap().to_vec(),
-        number_of_results: data.len() as u32,
-        page: query.page,
-        total_pages: total_pages,
-    }))
+    // cached results don't know about the new records
+    data.search_cache().clear();
+    tracing::info!(
+        "Added {} records, skipped {}",
+        report.inserted.len(),
+        report.skipped.len()
+    );
+
+    let status = if report.inserted.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((status, Json(report)))
+}
+
+pub async fn update_record_handler(
+    id: Result<Path<u32>, PathRejection>,
+    State(data): State<Arc<AppState>>,
+    body: Result<Json<UpdateRecordReq>, JsonRejection>,
+) -> Result<impl IntoResponse, AppError> {
+    let Path(id) = id?;
+    let Json(body) = body?;
+    let record = data
+        .db
+        .call(move |conn| Ok(edit::update_record(conn, id, body.record).map_err(AppError::from)?))
+        .await
+        .map_err(AppError::db("Failed to edit record"))?;
+
+    // cached results may hold the old version of the record
+    data.search_cache().clear();
+    tracing::info!("Updated record {}", id);
+
+    Ok(Json(record))
+}
+
+pub async fn delete_record_handler(
+    id: Result<Path<u32>, PathRejection>,
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let Path(id) = id?;
+    data.db
+        .call(move |conn| Ok(edit::delete_record(conn, id).map_err(AppError::from)?))
+        .await
+        .map_err(AppError::db("Failed to delete record"))?;
+
+    // cached results may still list the record
+    data.search_cache().clear();
+    tracing::info!("Deleted record {}", id);
+
+    Ok(StatusCode::NO_CONTENT)
 }
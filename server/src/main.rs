@@ -1,33 +1,52 @@
 pub mod bin;
+pub mod cache;
+pub mod error;
+pub mod explain;
 pub mod handler;
 pub mod model;
 pub mod schema;
+pub mod search;
+pub mod searches;
+pub mod snippet;
 
-use axum::{error_handling::HandleErrorLayer, http::StatusCode, routing, Router};
+use axum::{error_handling::HandleErrorLayer, routing, Router};
+use cache::{CacheConfig, SearchCache};
 use dotenv::dotenv;
-use handler::{search_handler, search_pagination_handler};
-use model::RecordResponse;
+use error::AppError;
+use handler::{
+    add_records_handler, delete_record_handler, label_conflicts_handler, recent_searches_handler,
+    search_handler, search_pagination_handler, update_record_handler,
+};
+use search::Boosts;
 use tokio_rusqlite;
 use tower::{BoxError, ServiceBuilder};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use uuid::Uuid;
 
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, MutexGuard},
     time::Duration,
 };
 
 pub struct AppState {
     db: tokio_rusqlite::Connection,
-    cached_queries: Mutex<HashMap<Uuid, QueryState>>,
+    search_cache: Mutex<SearchCache>,
+    /// field boosts of searches that don't set their own
+    boosts: Boosts,
 }
 
-#[allow(dead_code)]
-pub struct QueryState {
-    text_query: String,
-    data: Vec<RecordResponse>,
+impl AppState {
+    /// Locks the search cache. A request that panicked while holding it leaves it poisoned, in
+    /// which case it is emptied rather than failing every request after it.
+    fn search_cache(&self) -> MutexGuard<'_, SearchCache> {
+        self.search_cache.lock().unwrap_or_else(|poisoned| {
+            tracing::warn!("Search cache lock was poisoned, clearing the cache");
+            let mut cache = poisoned.into_inner();
+            cache.reset();
+            self.search_cache.clear_poison();
+            cache
+        })
+    }
 }
 
 #[tokio::main]
@@ -38,6 +57,9 @@ async fn main() {
     let conn = tokio_rusqlite::Connection::open(&database_file_name)
         .await
         .unwrap();
+    conn.call(|conn| Ok(searches::create_table(conn)?))
+        .await
+        .expect("Failed to create the searches table");
 
     tracing_subscriber::registry()
         .with(
@@ -53,20 +75,28 @@ async fn main() {
         .route("/test", routing::get(|| async { "this is a test" }))
         .route("/search", routing::get(search_handler))
         .route("/search-results", routing::get(search_pagination_handler))
+        .route("/searches/recent", routing::get(recent_searches_handler))
+        .route("/records", routing::post(add_records_handler))
+        .route(
+            "/records/:id",
+            routing::put(update_record_handler).delete(delete_record_handler),
+        )
+        .route(
+            "/reports/label-conflicts",
+            routing::get(label_conflicts_handler),
+        )
         .with_state(Arc::new(AppState {
             db: conn.clone(),
-            cached_queries: Mutex::new(HashMap::new()),
+            search_cache: Mutex::new(SearchCache::new(CacheConfig::from_env())),
+            boosts: Boosts::from_env(),
         }))
         .layer(
             ServiceBuilder::new()
                 .layer(HandleErrorLayer::new(|error: BoxError| async move {
                     if error.is::<tower::timeout::error::Elapsed>() {
-                        Ok(StatusCode::REQUEST_TIMEOUT)
+                        AppError::Timeout("Request took too long".to_string())
                     } else {
-                        Err((
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            format!("Unhandled internal error: {error}"),
-                        ))
+                        AppError::Internal(format!("Unhandled internal error: {error}"))
                     }
                 }))
                 .timeout(Duration::from_secs(10))
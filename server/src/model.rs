@@ -1,7 +1,12 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::bin::processing::Record;
+use crate::{
+    bin::processing::Record,
+    explain::{Explanation, Timings},
+    searches::SavedSearch,
+    snippet::Snippet,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SearchResultsRes {
@@ -9,23 +14,58 @@ pub struct SearchResultsRes {
     pub data: Vec<RecordResponse>,
     pub number_of_results: u32,
     pub page: u32,
+    pub page_size: u32,
     pub total_pages: u32,
+    /// pass as `cursor` to `/search` for the next page, none on the last page
+    pub next_cursor: Option<String>,
+    /// where the time of the request went, with `explain=true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecentSearchesRes {
+    pub searches: Vec<SavedSearch>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RecordResponse {
     pub data: Record,
     pub similar_docs: Vec<SimilarityInfoFull>,
+    /// the title and best matching passage of the text, with the query words highlighted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<Snippet>,
+    /// near-duplicates of the hit left out of the results, with `collapse=duplicates`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<CollapsedDuplicates>,
+    /// why the hit matched, with `explain=true`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CollapsedDuplicates {
+    pub count: u32,
+    /// in the order they would have had in the results
+    pub ids: Vec<u32>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct SimilarInfo {
     pub doc_id: i32,
     pub similarity: f32,
+    #[serde(default)]
+    pub containment: Option<f32>,
+    #[serde(default)]
+    pub reverse_containment: Option<f32>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct SimilarityInfoFull {
     pub doc: Record,
     pub similarity: f32,
+    /// how much of the hit is found in `doc`
+    pub containment: Option<f32>,
+    /// how much of `doc` is found in the hit
+    pub reverse_containment: Option<f32>,
 }
\ No newline at end of file
@@ -1,10 +1,33 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-// -- /api/search?query=<search_text>
+use crate::{bin::processing::append::NewRecord, search::Collapse, snippet::Highlight};
+
+// -- /api/search?search_text=<search_text>&page=<page>&page_size=<page_size>
 #[derive(Debug, Deserialize)]
 pub struct SearchReq {
     pub search_text: Option<String>,
+    /// comma separated metadata conditions, e.g. `source=reuters,year>=2020`
+    pub filter: Option<String>,
+    /// field to sort the results on, `-field` for descending, by relevance if left out
+    pub sort: Option<String>,
+    /// field boosts for relevance, e.g. `title:3,text:1`
+    pub boost: Option<String>,
+    /// `duplicates` to group near-duplicate hits under one of them
+    pub collapse: Option<Collapse>,
+    /// 1-based, defaults to the first page
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    /// `next_cursor` of a previous page, replaces `page` and `page_size`
+    pub cursor: Option<String>,
+    /// return the most similar documents of each hit, true by default
+    pub include_similar: Option<bool>,
+    /// return the full text of each hit next to its snippet, true by default
+    pub include_text: Option<bool>,
+    /// `offsets` (default) or `mark`
+    pub highlight: Option<Highlight>,
+    /// explain why each hit matched and report timings, false by default
+    pub explain: Option<bool>,
 }
 
 // -- /api/search-results?query_id=<search_id>&page=<page>
@@ -12,6 +35,17 @@ pub struct SearchReq {
 pub struct SearchResultsReq {
     pub query_id: Uuid,
     pub page: u32,
+    pub page_size: Option<u32>,
+    pub include_similar: Option<bool>,
+    pub include_text: Option<bool>,
+    pub highlight: Option<Highlight>,
+    pub explain: Option<bool>,
+}
+
+// -- /api/searches/recent?limit=<limit>
+#[derive(Debug, Deserialize)]
+pub struct RecentSearchesReq {
+    pub limit: Option<u32>,
 }
 
 // -- /api/get-record?id=<id>
@@ -19,3 +53,21 @@ pub struct SearchResultsReq {
 pub struct RecordReq {
     pub id: u32,
 }
+
+// -- POST /api/records
+#[derive(Debug, Deserialize)]
+pub struct AddRecordsReq {
+    pub records: Vec<NewRecord>,
+}
+
+// -- PUT /api/records/<id>
+#[derive(Debug, Deserialize)]
+pub struct UpdateRecordReq {
+    pub record: NewRecord,
+}
+
+// -- /api/reports/label-conflicts?min_similarity=<min_similarity>
+#[derive(Debug, Deserialize)]
+pub struct LabelConflictsReq {
+    pub min_similarity: Option<f64>,
+}
@@ -0,0 +1,771 @@
+use rusqlite::{params, params_from_iter, types::Value, Connection};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    time::Instant,
+};
+
+use crate::{
+    bin::processing::{
+        fields::{self, Field},
+        labels::LabelSet,
+        metadata::{
+            self, Filter, FilterOp, MetadataType, MetadataValue, SortKey, DUPLICATES_FIELD,
+        },
+        normalize::Normalization,
+        stable_hash, Record,
+    },
+    cache::SearchKey,
+    explain::{self, Timings},
+    model::{RecordResponse, SimilarInfo, SimilarityInfoFull},
+};
+
+const SIMILARITY_DOC_LIMIT: usize = 5;
+
+/// Why a search can't run.
+#[derive(Debug)]
+pub enum SearchError {
+    /// A query the database can't answer, with the reason.
+    Invalid(String),
+    Database(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for SearchError {
+    fn from(e: rusqlite::Error) -> Self {
+        SearchError::Database(e)
+    }
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::Invalid(reason) => f.write_str(reason),
+            SearchError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// A query word, limited to one field when written `field:word`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub field: Option<Field>,
+    pub word: String,
+}
+
+impl Term {
+    /// Reads a word of a `SearchKey`. Prefixes that aren't a field are part of the word.
+    pub fn parse(word: &str) -> Term {
+        if let Some((field, rest)) = word.split_once(':') {
+            if let (Ok(field), false) = (field.parse::<Field>(), rest.is_empty()) {
+                return Term {
+                    field: Some(field),
+                    word: rest.to_string(),
+                };
+            }
+        }
+        Term {
+            field: None,
+            word: word.to_string(),
+        }
+    }
+
+    /// Whether the term looks for matches in `field`.
+    pub fn searches(&self, field: Field) -> bool {
+        self.field.is_none_or(|own| own == field)
+    }
+}
+
+/// The words of a query: normalized like the indexed documents and lowercase like their
+/// tokens, `field:word` included.
+pub fn query_words(text: &str, normalization: &Normalization) -> Vec<String> {
+    normalization
+        .apply(text)
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// How much a match in each field adds to the relevance of a record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boosts {
+    pub title: f64,
+    pub text: f64,
+}
+
+impl Default for Boosts {
+    fn default() -> Self {
+        Boosts {
+            title: 2.0,
+            text: 1.0,
+        }
+    }
+}
+
+impl Boosts {
+    /// The defaults, or `SEARCH_BOOST_TITLE` and `SEARCH_BOOST_TEXT`.
+    pub fn from_env() -> Boosts {
+        fn var(name: &str) -> Option<f64> {
+            std::env::var(name)
+                .ok()?
+                .parse()
+                .ok()
+                .filter(|boost: &f64| boost.is_finite() && *boost >= 0.0)
+        }
+
+        let default = Boosts::default();
+        Boosts {
+            title: var("SEARCH_BOOST_TITLE").unwrap_or(default.title),
+            text: var("SEARCH_BOOST_TEXT").unwrap_or(default.text),
+        }
+    }
+
+    pub fn get(&self, field: Field) -> f64 {
+        match field {
+            Field::Title => self.title,
+            Field::Text => self.text,
+        }
+    }
+
+    /// `self` with the boosts given in `spec`, e.g. `title:3,text:0.5`.
+    pub fn with(mut self, spec: &str) -> Result<Boosts, String> {
+        for boost in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|boost| !boost.is_empty())
+        {
+            let invalid = || format!("invalid boost `{}`, expected field:number", boost);
+            let (field, value) = boost.split_once(':').ok_or_else(invalid)?;
+            let field: Field = field.trim().parse()?;
+            let value: f64 = value.trim().parse().map_err(|_| invalid())?;
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("boost of {} must be a non-negative number", field));
+            }
+            match field {
+                Field::Title => self.title = value,
+                Field::Text => self.text = value,
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Ids of an `entries` column, written as `[1, 2, 3]`.
+fn parse_entries(entries: &str) -> impl Iterator<Item = u32> + '_ {
+    entries
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(", ")
+        .filter_map(|id| id.parse::<u32>().ok())
+}
+
+/// Records with a token containing one of `terms`, with their relevance: for every term, the
+/// boosts of the fields it occurs in. Databases built before `field_index` existed count
+/// every match as a text match and can't limit terms to a field.
+pub fn scored_matches(
+    conn: &Connection,
+    terms: &[Term],
+    boosts: &Boosts,
+) -> Result<HashMap<u32, f64>, SearchError> {
+    let mut scores: HashMap<u32, f64> = HashMap::new();
+
+    if !fields::table_exists(conn, "field_index")? {
+        if let Some(term) = terms.iter().find(|term| term.field.is_some()) {
+            return Err(SearchError::Invalid(format!(
+                "`{}:` needs a field index, which this database was built without",
+                term.field.unwrap_or(Field::Text)
+            )));
+        }
+        let mut stmt =
+            conn.prepare_cached("SELECT entries FROM inverse_index WHERE string LIKE (?1)")?;
+        for term in terms {
+            let mut matched = BTreeSet::new();
+            let rows = stmt.query_map(params![format!("%{}%", term.word)], |row| {
+                row.get::<_, String>(0)
+            })?;
+            for entries in rows {
+                matched.extend(parse_entries(&entries?));
+            }
+            for id in matched {
+                *scores.entry(id).or_default() += boosts.text;
+            }
+        }
+        return Ok(scores);
+    }
+
+    let mut stmt =
+        conn.prepare_cached("SELECT field, entries FROM field_index WHERE string LIKE (?1)")?;
+    for term in terms {
+        // a term counts once per field, however many of the field's tokens contain it
+        let mut matched: BTreeSet<(u32, Field)> = BTreeSet::new();
+        let rows = stmt.query_map(params![format!("%{}%", term.word)], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (field, entries) = row?;
+            let Ok(field) = field.parse::<Field>() else {
+                continue;
+            };
+            if term.searches(field) {
+                matched.extend(parse_entries(&entries).map(|id| (id, field)));
+            }
+        }
+        for (id, field) in matched {
+            *scores.entry(id).or_default() += boosts.get(field);
+        }
+    }
+    Ok(scores)
+}
+
+/// How hits are grouped, `collapse=duplicates` being the only way so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Collapse {
+    Duplicates,
+}
+
+/// The hits of a search in result order.
+#[derive(Debug, Clone, Default)]
+pub struct Hits {
+    pub ids: Vec<u32>,
+    /// the near-duplicates grouped under each hit of `ids`, with `collapse=duplicates`
+    pub collapsed: Option<HashMap<u32, Vec<u32>>>,
+    /// set instead of `ids` for a browse SQLite pages itself
+    pub browse: Option<Browse>,
+}
+
+impl Hits {
+    pub fn new(ids: Vec<u32>) -> Hits {
+        Hits {
+            ids,
+            collapsed: None,
+            browse: None,
+        }
+    }
+
+    /// Number of hits.
+    pub fn count(&self) -> usize {
+        match &self.browse {
+            Some(browse) => browse.total,
+            None => self.ids.len(),
+        }
+    }
+
+    /// Ids of the hits in `range`, which must be within `count`.
+    pub fn page(&self, conn: &Connection, range: Range<usize>) -> rusqlite::Result<Vec<u32>> {
+        match &self.browse {
+            Some(browse) => browse.page(conn, range),
+            None => Ok(self.ids[range].to_vec()),
+        }
+    }
+}
+
+/// A search without words whose filters and sort only use core record fields. SQLite filters,
+/// orders and pages the `records` table itself, so no record is read before its page is.
+#[derive(Debug, Clone)]
+pub struct Browse {
+    conditions: String,
+    values: Vec<Value>,
+    order: String,
+    pub total: usize,
+}
+
+impl Browse {
+    /// The browse for `filters` and `sort_key`, or `None` if one of them is on a metadata field
+    /// or `duplicates`, which the `records` table doesn't have.
+    pub fn new(
+        conn: &Connection,
+        filters: &[Filter],
+        sort_key: Option<&SortKey>,
+    ) -> rusqlite::Result<Option<Browse>> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        for filter in filters {
+            let (Some(column), Some(value)) =
+                (core_column(&filter.field), sql_value(&filter.value))
+            else {
+                return Ok(None);
+            };
+            let op = match filter.op {
+                FilterOp::Eq => "=",
+                FilterOp::Ne => "!=",
+                FilterOp::Lt => "<",
+                FilterOp::Le => "<=",
+                FilterOp::Gt => ">",
+                FilterOp::Ge => ">=",
+            };
+            conditions.push(format!("{} {} ?", column, op));
+            values.push(value);
+        }
+        // ties stay in id order, like the relevance order of a browse
+        let order = match sort_key {
+            Some(sort_key) => {
+                let Some(column) = core_column(&sort_key.field) else {
+                    return Ok(None);
+                };
+                let direction = if sort_key.descending { "DESC" } else { "ASC" };
+                format!("{} {}, id", column, direction)
+            }
+            None => "id".to_string(),
+        };
+
+        let mut browse = Browse {
+            conditions: match conditions.is_empty() {
+                true => "1".to_string(),
+                false => conditions.join(" AND "),
+            },
+            values,
+            order,
+            total: 0,
+        };
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM records WHERE {}", browse.conditions),
+            params_from_iter(&browse.values),
+            |row| row.get(0),
+        )?;
+        browse.total = total as usize;
+        Ok(Some(browse))
+    }
+
+    /// Ids of the hits in `range`.
+    pub fn page(&self, conn: &Connection, range: Range<usize>) -> rusqlite::Result<Vec<u32>> {
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT id FROM records WHERE {} ORDER BY {} LIMIT ? OFFSET ?",
+            self.conditions, self.order
+        ))?;
+        let values = self.values.iter().cloned().chain([
+            Value::from(range.len() as i64),
+            Value::from(range.start as i64),
+        ]);
+        let ids = stmt.query_map(params_from_iter(values), |row| row.get(0))?;
+        ids.collect()
+    }
+
+    /// The hits, collapsed if `collapse` asks for it. Collapsing needs the whole order, which is
+    /// still only ids.
+    pub fn into_hits(
+        self,
+        conn: &Connection,
+        collapse: Option<Collapse>,
+    ) -> rusqlite::Result<Hits> {
+        match collapse {
+            Some(Collapse::Duplicates) => {
+                collapse_duplicates(conn, self.page(conn, 0..self.total)?)
+            }
+            None => Ok(Hits {
+                browse: Some(self),
+                ..Hits::default()
+            }),
+        }
+    }
+}
+
+/// SQL expression of a core record field in the `records` table, which keeps labels as text.
+fn core_column(field: &str) -> Option<&'static str> {
+    match field {
+        "id" => Some("id"),
+        "label" => Some("CAST(label AS INTEGER)"),
+        "title" => Some("title"),
+        // characters of the text, as `length` counts them for text values
+        "length" => Some("length(text)"),
+        _ => None,
+    }
+}
+
+fn sql_value(value: &MetadataValue) -> Option<Value> {
+    match value {
+        MetadataValue::Integer(value) => Some(Value::Integer(*value)),
+        MetadataValue::Text(value) => Some(Value::Text(value.clone())),
+        _ => None,
+    }
+}
+
+/// Groups hits that are near-duplicates according to `similarities` under the first of them in
+/// result order, which stays in the results in its place while the others are left out. A hit
+/// only absorbs its own neighbours, so every member of a group is similar to its representative.
+pub fn collapse_duplicates(conn: &Connection, ids: Vec<u32>) -> rusqlite::Result<Hits> {
+    let position: HashMap<u32, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+    let mut neighbours = near_duplicates(conn, &ids)?;
+    for similar in neighbours.values_mut() {
+        similar.retain(|similar| position.contains_key(similar));
+        similar.sort_by_key(|similar| position[similar]);
+    }
+
+    let mut grouped: BTreeSet<u32> = BTreeSet::new();
+    let mut representatives = Vec::new();
+    let mut collapsed = HashMap::new();
+    for id in ids {
+        if grouped.contains(&id) {
+            continue;
+        }
+        let members: Vec<u32> = neighbours
+            .remove(&id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|member| grouped.insert(*member))
+            .collect();
+        grouped.insert(id);
+        representatives.push(id);
+        collapsed.insert(id, members);
+    }
+
+    Ok(Hits {
+        ids: representatives,
+        collapsed: Some(collapsed),
+        browse: None,
+    })
+}
+
+/// The near-duplicates of each of `ids` according to `similarities`, not counting the record
+/// itself. Records without a `similarities` row are left out.
+fn near_duplicates(conn: &Connection, ids: &[u32]) -> rusqlite::Result<HashMap<u32, Vec<u32>>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT document_id, similar_documents FROM similarities
+         WHERE document_id IN (SELECT value FROM json_each(?1))",
+    )?;
+    let rows = stmt.query_map(params![json_ids(ids)], |row| {
+        Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut neighbours = HashMap::new();
+    for row in rows {
+        let (id, raw) = row?;
+        let similar: Vec<u32> = parse_similar_infos(&raw)
+            .into_iter()
+            .map(|info| info.doc_id as u32)
+            .filter(|similar| *similar != id)
+            .collect();
+        neighbours.insert(id, similar);
+    }
+    Ok(neighbours)
+}
+
+pub fn has_records(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row("SELECT EXISTS (SELECT 1 FROM records)", [], |row| {
+        row.get(0)
+    })
+}
+
+/// Every record of the database, all equally relevant, for searches without words.
+pub fn all_records(conn: &Connection) -> rusqlite::Result<HashMap<u32, f64>> {
+    let mut stmt = conn.prepare("SELECT id FROM records")?;
+    let rows = stmt.query_map([], |row| row.get::<_, u32>(0))?;
+    rows.map(|id| Ok((id?, 0.0))).collect()
+}
+
+/// Records with the given ids, with their label names and metadata, in two queries.
+fn fetch_records(
+    conn: &Connection,
+    metadata_fields: &HashMap<String, MetadataType>,
+    label_set: &LabelSet,
+    ids: &[u32],
+) -> rusqlite::Result<HashMap<u32, Record>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut stmt =
+        conn.prepare_cached("SELECT * FROM records WHERE id IN (SELECT value FROM json_each(?1))")?;
+    let rows = stmt.query_map(params![json_ids(ids)], |row| Record::try_from(row))?;
+    let mut metadata = metadata::load_many(conn, metadata_fields, ids)?;
+
+    let mut records = HashMap::new();
+    for record in rows {
+        let mut record = record?;
+        record.metadata = metadata.remove(&record.id).unwrap_or_default();
+        record.label_name = label_set.name(record.label).map(str::to_string);
+        records.insert(record.id, record);
+    }
+    Ok(records)
+}
+
+/// `ids` as a json array, bound to `json_each` to query many ids at once.
+fn json_ids(ids: &[u32]) -> String {
+    serde_json::to_string(ids).expect("ids serialize")
+}
+
+/// The search hits in result order: by relevance, then id, unless `sort_key` is given, so
+/// that the same search always pages the same way. `sort_key` keeps relevance order among ties.
+/// Records are only read when `filters` or `sort_key` need them.
+pub fn ordered_hits(
+    conn: &Connection,
+    scores: HashMap<u32, f64>,
+    filters: &[Filter],
+    sort_key: Option<&SortKey>,
+    metadata_fields: &HashMap<String, MetadataType>,
+) -> rusqlite::Result<Vec<u32>> {
+    let mut ranked: Vec<(u32, f64)> = scores.into_iter().collect();
+    ranked.sort_by(|(a, a_score), (b, b_score)| b_score.total_cmp(a_score).then(a.cmp(b)));
+    let ids: Vec<u32> = ranked.into_iter().map(|(id, _)| id).collect();
+    if filters.is_empty() && sort_key.is_none() {
+        return Ok(ids);
+    }
+
+    let label_set = LabelSet::load(conn)?;
+    let mut records = fetch_records(conn, metadata_fields, &label_set, &ids)?;
+    let uses_duplicates = filters
+        .iter()
+        .map(|filter| &filter.field)
+        .chain(sort_key.map(|sort_key| &sort_key.field))
+        .any(|field| field == DUPLICATES_FIELD);
+    if uses_duplicates {
+        let mut counts = near_duplicates(conn, &ids)?;
+        for (id, record) in records.iter_mut() {
+            let count = counts.remove(id).map_or(0, |similar| similar.len());
+            record.metadata.insert(
+                DUPLICATES_FIELD.to_string(),
+                MetadataValue::Integer(count as i64),
+            );
+        }
+    }
+    let mut records: Vec<Record> = ids
+        .iter()
+        .filter_map(|id| records.remove(id))
+        .filter(|record| filters.iter().all(|filter| filter.matches(record)))
+        .collect();
+    if let Some(sort_key) = sort_key {
+        // stable, so ties keep relevance order
+        records.sort_by(|a, b| sort_key.compare(a, b));
+    }
+
+    Ok(records.into_iter().map(|record| record.id).collect())
+}
+
+/// Full results for the given hits, with their most similar documents if `include_similar`.
+/// Takes a fixed number of queries however many hits and neighbours there are.
+pub fn load_page(
+    conn: &Connection,
+    ids: &[u32],
+    metadata_fields: &HashMap<String, MetadataType>,
+    include_similar: bool,
+    timings: &mut Timings,
+) -> rusqlite::Result<Vec<RecordResponse>> {
+    let started = Instant::now();
+    let label_set = LabelSet::load(conn)?;
+    let mut records = fetch_records(conn, metadata_fields, &label_set, ids)?;
+    timings.record_fetch_ms = explain::since(started);
+
+    let started = Instant::now();
+    let mut similar_rows: HashMap<u32, Vec<SimilarInfo>> = HashMap::new();
+    if include_similar {
+        let mut stmt_similar_docs = conn.prepare_cached(
+            "SELECT document_id, similar_documents FROM similarities
+             WHERE document_id IN (SELECT value FROM json_each(?1))",
+        )?;
+        let rows = stmt_similar_docs.query_map(params![json_ids(ids)], |row| {
+            Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (id, raw) = row?;
+            let mut infos = parse_similar_infos(&raw);
+            infos.truncate(SIMILARITY_DOC_LIMIT);
+            similar_rows.insert(id, infos);
+        }
+
+        let neighbours: Vec<u32> = similar_rows
+            .values()
+            .flatten()
+            .map(|info| info.doc_id as u32)
+            .filter(|id| !records.contains_key(id))
+            .collect::<BTreeSet<u32>>()
+            .into_iter()
+            .collect();
+        let neighbours = fetch_records(conn, metadata_fields, &label_set, &neighbours)?;
+        records.extend(neighbours);
+    }
+    timings.similarity_fetch_ms = explain::since(started);
+
+    Ok(ids
+        .iter()
+        .filter(|id| records.contains_key(id))
+        .map(|id| RecordResponse {
+            data: records[id].clone(),
+            similar_docs: similar_rows
+                .remove(id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|r| {
+                    Some(SimilarityInfoFull {
+                        doc: records.get(&(r.doc_id as u32))?.clone(),
+                        similarity: r.similarity,
+                        containment: r.containment,
+                        reverse_containment: r.reverse_containment,
+                    })
+                })
+                .collect(),
+            snippet: None,
+            duplicates: None,
+            explanation: None,
+        })
+        .collect())
+}
+
+/// Parses the `similar_documents` column, written as `[{..},{..},]`.
+fn parse_similar_infos(raw: &str) -> Vec<SimilarInfo> {
+    raw.trim()
+        .trim_start_matches("[{")
+        .trim_end_matches("},]")
+        .split("},{")
+        .filter_map(|e| serde_json::from_str::<SimilarInfo>(&format!("{{{}}}", e)).ok())
+        .collect()
+}
+
+/// Position in the results of a search, handed out as an opaque token so clients can walk the
+/// pages without the server keeping any state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub page: u32,
+    pub page_size: u32,
+    /// ties the cursor to the search it was made for
+    fingerprint: u64,
+}
+
+impl Cursor {
+    pub fn new(key: &SearchKey, page: u32, page_size: u32) -> Cursor {
+        Cursor {
+            page,
+            page_size,
+            fingerprint: fingerprint(key),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}:{}:{:x}", self.page, self.page_size, self.fingerprint)
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Reads a cursor made by `encode` for the search `key`.
+    pub fn decode(raw: &str, key: &SearchKey) -> Result<Cursor, String> {
+        let invalid = || "Invalid cursor".to_string();
+
+        let bytes = (0..raw.len())
+            .step_by(2)
+            .map(|i| {
+                raw.get(i..i + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let mut parts = text.split(':');
+        let (Some(page), Some(page_size), Some(fingerprint), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        let cursor = Cursor {
+            page: page.parse().map_err(|_| invalid())?,
+            page_size: page_size.parse().map_err(|_| invalid())?,
+            fingerprint: u64::from_str_radix(fingerprint, 16).map_err(|_| invalid())?,
+        };
+        if cursor.fingerprint != self::fingerprint(key) {
+            return Err("Cursor belongs to a different search".to_string());
+        }
+        Ok(cursor)
+    }
+}
+
+/// Stable hash of the serialized key. Cursors outlive the server that issued them, so it must
+/// not change between builds.
+fn fingerprint(key: &SearchKey) -> u64 {
+    stable_hash(&serde_json::to_vec(key).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(words: &[&str], filter: Option<&str>) -> SearchKey {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        SearchKey::new(&words, filter, None, None, None)
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let key = key(&["storm"], Some("label=1"));
+        let cursor = Cursor::new(&key, 3, 25);
+        assert_eq!(Cursor::decode(&cursor.encode(), &key), Ok(cursor));
+    }
+
+    #[test]
+    fn cursors_are_tied_to_their_search() {
+        let cursor = Cursor::new(&key(&["storm"], None), 1, 10).encode();
+        assert_eq!(
+            Cursor::decode(&cursor, &key(&["storm"], Some("label=1"))),
+            Err("Cursor belongs to a different search".to_string())
+        );
+        for raw in ["", "zz", "abc", "313a32"] {
+            assert_eq!(
+                Cursor::decode(raw, &key(&["storm"], None)),
+                Err("Invalid cursor".to_string()),
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn fingerprints_are_stable() {
+        // cursors handed out by earlier builds must still decode
+        assert_eq!(fingerprint(&key(&["storm"], None)), 0x4af7_7642_783c_bd45);
+    }
+
+    fn browse(conn: &Connection, filter: &str, sort_key: Option<&SortKey>) -> Hits {
+        let filters =
+            metadata::parse_filters(filter, &HashMap::new(), &LabelSet::load(conn).unwrap())
+                .unwrap();
+        Browse::new(conn, &filters, sort_key)
+            .unwrap()
+            .unwrap()
+            .into_hits(conn, None)
+            .unwrap()
+    }
+
+    #[test]
+    fn browses_filter_sort_and_page_in_sql() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE records (id INTEGER PRIMARY KEY NOT NULL, title TEXT, text TEXT, label TEXT);
+             INSERT INTO records VALUES (1, 'a', 'short', '1'), (2, 'b', 'the longest text', '0'),
+                 (3, 'c', 'a longer text', '1'), (4, 'd', 'tiny', '10');
+             CREATE TABLE labels ( id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE );
+             INSERT INTO labels VALUES (0, 'negative'), (1, 'positive'), (10, 'mixed');",
+        )
+        .unwrap();
+
+        let sort_key = SortKey {
+            field: "length".to_string(),
+            descending: true,
+        };
+        let hits = browse(&conn, "label>=1", Some(&sort_key));
+        assert_eq!(hits.count(), 3);
+        assert_eq!(hits.page(&conn, 0..3).unwrap(), vec![3, 1, 4]);
+        assert_eq!(hits.page(&conn, 1..2).unwrap(), vec![1]);
+
+        // names stand for their ids
+        let hits = browse(&conn, "label=positive", None);
+        assert_eq!(hits.page(&conn, 0..2).unwrap(), vec![1, 3]);
+        let hits = browse(&conn, "label!=mixed", None);
+        assert_eq!(hits.page(&conn, 0..3).unwrap(), vec![1, 2, 3]);
+        assert!(metadata::parse_filters(
+            "label=neutral",
+            &HashMap::new(),
+            &LabelSet::load(&conn).unwrap()
+        )
+        .is_err());
+
+        let on_metadata = SortKey {
+            field: "source".to_string(),
+            descending: false,
+        };
+        assert!(Browse::new(&conn, &[], Some(&on_metadata))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,133 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{cache::SearchKey, search::Collapse};
+
+/// A search as stored in the `searches` table, so its id can be resolved after the cached
+/// results are gone or the server restarted.
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSearch {
+    pub search_id: Uuid,
+    /// the query as it was typed
+    pub search_text: String,
+    pub filter: Option<String>,
+    pub sort: Option<String>,
+    pub boost: Option<String>,
+    pub collapse: Option<Collapse>,
+    pub number_of_results: u32,
+    /// UTC, `YYYY-MM-DD HH:MM:SS`
+    pub created_at: String,
+    pub last_run_at: String,
+    #[serde(skip)]
+    pub key: SearchKey,
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS searches (
+             id TEXT PRIMARY KEY,
+             search_key TEXT NOT NULL UNIQUE,
+             search_text TEXT NOT NULL,
+             number_of_results INTEGER NOT NULL,
+             created_at TEXT NOT NULL DEFAULT (datetime('now')),
+             last_run_at TEXT NOT NULL DEFAULT (datetime('now'))
+         );
+         CREATE INDEX IF NOT EXISTS searches_last_run_at ON searches (last_run_at);",
+    )
+}
+
+/// Records a run of the search `key` and returns its id. Searches that were run before keep
+/// their id, so links to them stay valid.
+pub fn record_run(
+    conn: &Connection,
+    key: &SearchKey,
+    search_text: &str,
+    number_of_results: usize,
+) -> rusqlite::Result<Uuid> {
+    let stored_key = serde_json::to_string(key).expect("search keys serialize");
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT id FROM searches WHERE search_key = (?1)",
+            params![stored_key],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    match existing.and_then(|id| Uuid::parse_str(&id).ok()) {
+        Some(id) => {
+            conn.execute(
+                "UPDATE searches SET number_of_results = (?2), last_run_at = datetime('now')
+                 WHERE id = (?1)",
+                params![id.to_string(), number_of_results as i64],
+            )?;
+            Ok(id)
+        }
+        None => {
+            let id = Uuid::new_v4();
+            conn.execute(
+                "INSERT INTO searches (id, search_key, search_text, number_of_results)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    id.to_string(),
+                    stored_key,
+                    search_text,
+                    number_of_results as i64
+                ],
+            )?;
+            Ok(id)
+        }
+    }
+}
+
+const SEARCH_COLUMNS: &str =
+    "id, search_key, search_text, number_of_results, created_at, last_run_at";
+
+fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Option<SavedSearch>> {
+    let id: String = row.get(0)?;
+    let stored_key: String = row.get(1)?;
+    let (Ok(search_id), Ok(key)) = (
+        Uuid::parse_str(&id),
+        serde_json::from_str::<SearchKey>(&stored_key),
+    ) else {
+        return Ok(None);
+    };
+
+    Ok(Some(SavedSearch {
+        search_id,
+        search_text: row.get(2)?,
+        filter: key.filter().map(str::to_string),
+        sort: key.sort().map(str::to_string),
+        boost: key.boost().map(str::to_string),
+        collapse: key.collapse(),
+        number_of_results: row.get(3)?,
+        created_at: row.get(4)?,
+        last_run_at: row.get(5)?,
+        key,
+    }))
+}
+
+pub fn load(conn: &Connection, id: Uuid) -> rusqlite::Result<Option<SavedSearch>> {
+    conn.query_row(
+        &format!("SELECT {} FROM searches WHERE id = (?1)", SEARCH_COLUMNS),
+        params![id.to_string()],
+        from_row,
+    )
+    .optional()
+    .map(Option::flatten)
+}
+
+/// The `limit` most recently run searches, latest first.
+pub fn recent(conn: &Connection, limit: u32) -> rusqlite::Result<Vec<SavedSearch>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM searches ORDER BY last_run_at DESC, rowid DESC LIMIT (?1)",
+        SEARCH_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![limit], from_row)?;
+
+    let mut searches = Vec::new();
+    for row in rows {
+        searches.extend(row?);
+    }
+    Ok(searches)
+}
@@ -0,0 +1,281 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bin::processing::{fields::Field, Record},
+    search::Term,
+};
+
+/// Characters of text around the best matching passage.
+const SNIPPET_LENGTH: usize = 200;
+/// Characters kept before the first match of the passage.
+const LEADING_CONTEXT: usize = 40;
+const ELLIPSIS: &str = "…";
+
+/// How matches are marked in a snippet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Highlight {
+    /// plain text with `[start, end)` character offsets of every match
+    #[default]
+    Offsets,
+    /// html escaped text with matches wrapped in `<mark>` tags
+    Mark,
+}
+
+/// Why a record matched: its title and the passage of its text with the most query words.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snippet {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub title_matches: Vec<[usize; 2]>,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub text_matches: Vec<[usize; 2]>,
+}
+
+/// Lowercases one character to one character, so offsets in the lowercased text are offsets in
+/// the original.
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Character ranges of every occurrence of `words` in `chars`, sorted, with the index of the
+/// word each one matched. Words match anywhere in a token, like the search itself.
+fn find_matches(chars: &[char], words: &[Vec<char>]) -> Vec<(usize, usize, usize)> {
+    let folded: Vec<char> = chars.iter().copied().map(fold).collect();
+    let mut matches = Vec::new();
+
+    for (index, word) in words.iter().enumerate() {
+        if word.is_empty() || word.len() > folded.len() {
+            continue;
+        }
+        let mut start = 0;
+        while start + word.len() <= folded.len() {
+            if folded[start..start + word.len()] == word[..] {
+                matches.push((start, start + word.len(), index));
+                start += word.len();
+            } else {
+                start += 1;
+            }
+        }
+    }
+
+    matches.sort();
+    // overlapping matches of different words are merged into the first
+    let mut merged: Vec<(usize, usize, usize)> = Vec::new();
+    for m in matches {
+        match merged.last_mut() {
+            Some(last) if m.0 < last.1 => last.1 = last.1.max(m.1),
+            _ => merged.push(m),
+        }
+    }
+    merged
+}
+
+/// Start of the `SNIPPET_LENGTH` window covering the most distinct words, then the most matches.
+fn best_window(matches: &[(usize, usize, usize)]) -> usize {
+    let mut best = (0, 0, 0);
+    for (i, (start, _, _)) in matches.iter().enumerate() {
+        let window_start = start.saturating_sub(LEADING_CONTEXT);
+        let in_window: Vec<_> = matches[i..]
+            .iter()
+            .take_while(|(_, end, _)| *end <= window_start + SNIPPET_LENGTH)
+            .collect();
+        let mut distinct: Vec<usize> = in_window.iter().map(|(_, _, word)| *word).collect();
+        distinct.sort();
+        distinct.dedup();
+
+        let score = (distinct.len(), in_window.len());
+        if score > (best.0, best.1) {
+            best = (score.0, score.1, window_start);
+        }
+    }
+    best.2
+}
+
+/// Moves `position` back to the start of the word it falls in.
+fn word_start(chars: &[char], mut position: usize) -> usize {
+    while position > 0 && !chars[position - 1].is_whitespace() {
+        position -= 1;
+    }
+    position
+}
+
+/// Moves `position` forward to the end of the word it falls in.
+fn word_end(chars: &[char], mut position: usize) -> usize {
+    while position < chars.len() && !chars[position].is_whitespace() {
+        position += 1;
+    }
+    position
+}
+
+/// `chars[start..end]` with its matches, marked or as offsets, and ellipses where it was cut.
+fn render(
+    chars: &[char],
+    start: usize,
+    end: usize,
+    matches: &[(usize, usize, usize)],
+    highlight: Highlight,
+) -> (String, Vec<[usize; 2]>) {
+    let mut text = String::new();
+    let mut offsets = Vec::new();
+    let mut length = 0;
+
+    if start > 0 {
+        push(&mut text, ELLIPSIS, &mut length);
+    }
+    let mut position = start;
+    for &(match_start, match_end, _) in matches {
+        if match_start < start || match_end > end {
+            continue;
+        }
+        let before: String = chars[position..match_start].iter().collect();
+        let matched: String = chars[match_start..match_end].iter().collect();
+        match highlight {
+            Highlight::Offsets => {
+                push(&mut text, &before, &mut length);
+                offsets.push([length, length + (match_end - match_start)]);
+                push(&mut text, &matched, &mut length);
+            }
+            Highlight::Mark => {
+                text.push_str(&escape_html(&before));
+                text.push_str("<mark>");
+                text.push_str(&escape_html(&matched));
+                text.push_str("</mark>");
+            }
+        }
+        position = match_end;
+    }
+    let rest: String = chars[position..end].iter().collect();
+    match highlight {
+        Highlight::Offsets => push(&mut text, &rest, &mut length),
+        Highlight::Mark => text.push_str(&escape_html(&rest)),
+    }
+    if end < chars.len() {
+        push(&mut text, ELLIPSIS, &mut length);
+    }
+
+    (text, offsets)
+}
+
+/// Appends `piece` to `text`, counting the characters for the offsets of later matches.
+fn push(text: &mut String, piece: &str, length: &mut usize) {
+    text.push_str(piece);
+    *length += piece.chars().count();
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Snippet of `record` for a search for `words` (the words of its `SearchKey`).
+pub fn snippet(record: &Record, words: &[String], highlight: Highlight) -> Snippet {
+    let terms: Vec<Term> = words.iter().map(|word| Term::parse(word)).collect();
+    // `title:` terms are only highlighted in the title, `text:` ones in the text
+    let words_in = |field: Field| -> Vec<Vec<char>> {
+        terms
+            .iter()
+            .map(|term| match term.searches(field) {
+                true => term.word.chars().map(fold).collect(),
+                false => Vec::new(),
+            })
+            .collect()
+    };
+
+    let title: Vec<char> = record.title.chars().collect();
+    let title_matches = find_matches(&title, &words_in(Field::Title));
+    let (title, title_matches) = render(&title, 0, title.len(), &title_matches, highlight);
+
+    let text: Vec<char> = record.text.chars().collect();
+    let text_matches = find_matches(&text, &words_in(Field::Text));
+    let window_start = word_start(&text, best_window(&text_matches));
+    let window_end = word_end(&text, (window_start + SNIPPET_LENGTH).min(text.len()));
+    let (text, text_matches) = render(&text, window_start, window_end, &text_matches, highlight);
+
+    Snippet {
+        title,
+        title_matches,
+        text,
+        text_matches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bin::processing::test_support::record;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn offsets_point_at_every_match() {
+        let record = record(
+            1,
+            "Storm warning",
+            "A storm hit the coast. Storms again.",
+            0,
+        );
+        let snippet = snippet(&record, &words(&["storm"]), Highlight::Offsets);
+
+        assert_eq!(snippet.title, "Storm warning");
+        assert_eq!(snippet.title_matches, vec![[0, 5]]);
+        assert_eq!(snippet.text, record.text);
+        assert_eq!(snippet.text_matches, vec![[2, 7], [23, 28]]);
+    }
+
+    #[test]
+    fn field_terms_only_match_their_field() {
+        let record = record(1, "Coast storm", "The coast after the storm", 0);
+        let snippet = snippet(
+            &record,
+            &words(&["title:coast", "storm"]),
+            Highlight::Offsets,
+        );
+
+        assert_eq!(snippet.title_matches, vec![[0, 5], [6, 11]]);
+        assert_eq!(snippet.text_matches, vec![[20, 25]]);
+    }
+
+    #[test]
+    fn marks_escape_the_text_around_matches() {
+        let record = record(1, "Fish & <b>chips</b>", "\"chips\"", 0);
+        let snippet = snippet(&record, &words(&["chips"]), Highlight::Mark);
+
+        assert_eq!(
+            snippet.title,
+            "Fish &amp; &lt;b&gt;<mark>chips</mark>&lt;/b&gt;"
+        );
+        assert!(snippet.title_matches.is_empty());
+        assert_eq!(snippet.text, "&quot;<mark>chips</mark>&quot;");
+    }
+
+    #[test]
+    fn long_texts_are_cut_around_the_best_passage() {
+        let text = format!(
+            "{}storm coast {}",
+            "filler ".repeat(100),
+            "filler ".repeat(100)
+        );
+        let snippet = snippet(
+            &record(1, "", &text, 0),
+            &words(&["storm", "coast"]),
+            Highlight::Offsets,
+        );
+
+        assert!(snippet.text.starts_with(ELLIPSIS));
+        assert!(snippet.text.ends_with(ELLIPSIS));
+        let chars: Vec<char> = snippet.text.chars().collect();
+        assert!(chars.len() <= SNIPPET_LENGTH + 2 * ELLIPSIS.chars().count() + "filler".len());
+        let matched: Vec<String> = snippet
+            .text_matches
+            .iter()
+            .map(|[start, end]| chars[*start..*end].iter().collect())
+            .collect();
+        assert_eq!(matched, vec!["storm", "coast"]);
+    }
+}